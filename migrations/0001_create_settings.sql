@@ -0,0 +1,6 @@
+-- User settings, one JSON document per user.
+CREATE TABLE IF NOT EXISTS settings (
+  uid        BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+  content    JSON            NOT NULL,
+  event_time BIGINT UNSIGNED NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Version counter behind ETag / If-Match. Existing rows start at 1; version 0
+-- is reserved for documents that do not exist.
+ALTER TABLE settings ADD COLUMN version BIGINT UNSIGNED NOT NULL DEFAULT 1 AFTER content;
@@ -1,6 +1,12 @@
 // src/config.rs
+use crate::utils::clio::ClioProvider;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, fs, path::PathBuf};
+use toml::{Table, Value};
+
+const CONFIG_PATH: &str = "config/config.toml";
+const ENV_PREFIX: &str = "RP_";
+const MODES: [&str; 2] = ["release", "test"];
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct Config {
@@ -9,140 +15,721 @@ pub struct Config {
     pub env: String,
     pub log: String,
     pub port: String,
+    pub reload_interval: u64,
+    pub health_interval: u64,
+    pub degraded_start: bool,
+    pub shutdown_timeout: u64,
+    pub settings_store: StoreKind,
+    pub settings_cache_write: CacheWrite,
+    pub batch: BatchConf,
+    pub migrate_on_start: bool,
+    pub fetch: FetchConf,
 }
 
-#[derive(Deserialize, Clone, Debug)]
-pub struct RedisConfig {
-    pub profile: String,
+// Named Redis instances and MySQL clusters, keyed by their [redis.<name>] /
+// [mysql.<name>] section.
+pub type RedisConfig = HashMap<String, RedisConf>;
+pub type MysqlConfig = HashMap<String, DbConf>;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct DbConf {
+    pub master: String,
+    pub slaves: Vec<String>,
+    pub balance: Balance,
+    pub pool: PoolConf,
 }
 
-#[derive(Deserialize, Clone, Debug)]
-pub struct MysqlConfig {
-    pub relation: DbConf,
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RedisConf {
+    pub url: String,
+    pub pool: PoolConf,
 }
 
-#[derive(Deserialize, Clone, Debug)]
-pub struct DbConf {
-    pub master: String,
-    pub slave: String,
+// Most uids a single batch request may carry.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BatchConf {
+    pub get_max: usize,
+    pub set_max: usize,
+}
+
+// Outbound HTTP calls made through `Fetch`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct FetchConf {
+    pub retry: RetryConf,
+    pub breaker: BreakerConf,
+}
+
+// Backoffs are in milliseconds: retry n waits base_backoff * 2^(n-1), capped
+// at max_backoff, of which up to `jitter` (0 to 1) is taken off at random.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RetryConf {
+    pub max_attempts: u32,
+    pub base_backoff: u64,
+    pub max_backoff: u64,
+    pub jitter: f64,
+    // Business codes in the response body worth another attempt.
+    pub retry_codes: Vec<i64>,
+    // POST and PATCH are only retried when set; not read from the config file
+    // since only the caller knows whether a call is safe to repeat.
+    pub non_idempotent: bool,
+}
+
+impl Default for RetryConf {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_backoff: 100,
+            max_backoff: 2000,
+            jitter: 0.5,
+            retry_codes: Vec::new(),
+            non_idempotent: false,
+        }
+    }
+}
+
+// A host's circuit opens once `failure_rate` (0 to 1] of at least
+// `min_requests` calls within `window` seconds failed, rejects calls for
+// `cooldown` seconds, then lets `half_open_probes` calls through to decide
+// whether to close again.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BreakerConf {
+    pub failure_rate: f64,
+    pub window: u64,
+    pub min_requests: u32,
+    pub cooldown: u64,
+    pub half_open_probes: u32,
+}
+
+impl Default for BreakerConf {
+    fn default() -> Self {
+        Self { failure_rate: 0.5, window: 10, min_requests: 10, cooldown: 30, half_open_probes: 1 }
+    }
+}
+
+// Durations are in seconds; 0 disables idle_timeout and max_lifetime.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PoolConf {
+    pub min_size: u32,
+    pub max_size: u32,
+    pub acquire_timeout: u64,
+    pub idle_timeout: u64,
+    pub max_lifetime: u64,
+    pub test_on_acquire: bool,
+}
+
+impl PoolConf {
+    const MYSQL: Self = Self {
+        min_size: 0,
+        max_size: 10,
+        acquire_timeout: 30,
+        idle_timeout: 600,
+        max_lifetime: 1800,
+        test_on_acquire: true,
+    };
+    const REDIS: Self =
+        Self { min_size: 0, max_size: 40, acquire_timeout: 1, idle_timeout: 0, max_lifetime: 0, test_on_acquire: true };
+
+    fn validate(&self, scope: &str, problems: &mut Vec<String>) {
+        if self.max_size == 0 {
+            problems.push(format!("{}.max_size must be greater than 0", scope));
+        }
+        if self.min_size > self.max_size {
+            problems.push(format!("{}.min_size ({}) exceeds max_size ({})", scope, self.min_size, self.max_size));
+        }
+        if self.acquire_timeout == 0 {
+            problems.push(format!("{}.acquire_timeout must be greater than 0", scope));
+        }
+        if self.max_lifetime > 0 && self.idle_timeout > self.max_lifetime {
+            problems.push(format!("{}.idle_timeout exceeds max_lifetime", scope));
+        }
+    }
+}
+
+// Backend behind the settings endpoints.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreKind {
+    Mysql,
+    Redis,
+    #[default]
+    CacheAside,
+}
+
+// What a cache_aside write does to the Redis copy once MySQL has committed.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheWrite {
+    #[default]
+    Invalidate,
+    Update,
+}
+
+// How reads are spread over the healthy replicas of a cluster.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Balance {
+    #[default]
+    RoundRobin,
+    LeastConnections,
 }
 
 #[derive(Clone, Debug)]
 pub struct ConfMySQL {
-    pub master: &'static str,
-    pub slave: &'static str,
-    pub username: &'static str,
-    pub password: &'static str,
-    pub database: &'static str,
+    pub master: String,
+    pub slaves: Vec<String>,
+    pub username: String,
+    pub password: String,
+    pub database: String,
+    pub balance: Balance,
+    pub pool: PoolConf,
 }
 
 #[derive(Clone, Debug)]
-#[allow(dead_code)]
 pub struct ConfRedis {
-    pub host: &'static str,
-    pub password: &'static str,
+    pub host: String,
+    pub password: String,
+    pub pool: PoolConf,
 }
 
-impl Config {
-    pub fn init() -> Self {
-        let cache = Self::get_redis_config()["default"].clone();
-        let db = Self::get_mysql_config()["default"].clone();
-
-        let profile = Self::create_redis_uri(cache.host.to_string());
-        let relation = Self::create_mysql_uri(
-            db.username.to_string(),
-            db.password.to_string(),
-            db.master.to_string(),
-            db.slave.to_string(),
-            db.database.to_string(),
-        );
+// The shape of the merged config file, every key optional so that all
+// problems can be collected before giving up.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    log: Option<String>,
+    port: Option<String>,
+    reload_interval: Option<u64>,
+    health_interval: Option<u64>,
+    degraded_start: Option<bool>,
+    shutdown_timeout: Option<u64>,
+    migrate_on_start: Option<bool>,
+    #[serde(default)]
+    settings_store: StoreKind,
+    #[serde(default)]
+    settings_cache_write: CacheWrite,
+    #[serde(default)]
+    batch: RawBatch,
+    #[serde(default)]
+    fetch: RawFetch,
+    #[serde(default)]
+    clio: RawClio,
+    #[serde(default)]
+    mysql: HashMap<String, RawMySQL>,
+    #[serde(default)]
+    redis: HashMap<String, RawRedis>,
+}
 
-        Self {
-            cache: RedisConfig { profile },
-            db: MysqlConfig { relation },
-            env: Self::get_mode(),
-            log: std::env::var("LOG_DIR").unwrap_or_else(|_| "/data/logs/rust-practice".into()),
-            port: std::env::var("KS_PORT").unwrap_or_else(|_| "8887".into()),
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawBatch {
+    get_max: Option<usize>,
+    set_max: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawFetch {
+    #[serde(default)]
+    retry: RawRetry,
+    #[serde(default)]
+    breaker: RawBreaker,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawRetry {
+    max_attempts: Option<u32>,
+    base_backoff: Option<u64>,
+    max_backoff: Option<u64>,
+    jitter: Option<f64>,
+    retry_codes: Option<Vec<i64>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawBreaker {
+    failure_rate: Option<f64>,
+    window: Option<u64>,
+    min_requests: Option<u32>,
+    cooldown: Option<u64>,
+    half_open_probes: Option<u32>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawClio {
+    binary: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawMySQL {
+    master: Option<String>,
+    slaves: Option<Vec<String>>,
+    clio_master: Option<String>,
+    clio_slaves: Option<String>,
+    #[serde(default)]
+    balance: Balance,
+    username: Option<String>,
+    password: Option<String>,
+    database: Option<String>,
+    #[serde(default)]
+    pool: RawPool,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawRedis {
+    host: Option<String>,
+    clio_host: Option<String>,
+    password: Option<String>,
+    #[serde(default)]
+    pool: RawPool,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawPool {
+    min_size: Option<u32>,
+    max_size: Option<u32>,
+    acquire_timeout: Option<u64>,
+    idle_timeout: Option<u64>,
+    max_lifetime: Option<u64>,
+    test_on_acquire: Option<bool>,
+}
+
+impl RawPool {
+    fn or(self, default: PoolConf) -> PoolConf {
+        PoolConf {
+            min_size: self.min_size.unwrap_or(default.min_size),
+            max_size: self.max_size.unwrap_or(default.max_size),
+            acquire_timeout: self.acquire_timeout.unwrap_or(default.acquire_timeout),
+            idle_timeout: self.idle_timeout.unwrap_or(default.idle_timeout),
+            max_lifetime: self.max_lifetime.unwrap_or(default.max_lifetime),
+            test_on_acquire: self.test_on_acquire.unwrap_or(default.test_on_acquire),
         }
     }
+}
 
-    fn get_mode() -> String {
-        match std::env::var("RP_MODE") {
-            Ok(v) if v == "release" || v == "test" => v,
-            _ => "test".to_string(),
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => {
+                write!(f, "Failed to read config {}: {}", path.display(), e)
+            }
+            ConfigError::Parse(path, e) => {
+                write!(f, "Failed to parse config {}: {}", path.display(), e)
+            }
+            ConfigError::Invalid(problems) => {
+                write!(f, "Invalid config ({} problems):", problems.len())?;
+                for p in problems {
+                    write!(f, "\n  - {}", p)?;
+                }
+                Ok(())
+            }
         }
     }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn init() -> Result<Self, ConfigError> {
+        let path = std::env::var("RP_CONFIG").map(PathBuf::from).unwrap_or_else(|_| CONFIG_PATH.into());
+        Self::load(path, Self::get_mode())
+    }
+
+    // file → [mode] section → LOG_DIR / KS_PORT → RP_* variables
+    pub fn load(path: PathBuf, mode: String) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(&path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+        let mut table: Table = toml::from_str(&text).map_err(|e| ConfigError::Parse(path.clone(), e.to_string()))?;
+
+        let mut problems = Vec::new();
+        if !MODES.contains(&mode.as_str()) {
+            problems.push(format!("RP_MODE must be one of {}, got {:?}", MODES.join(", "), mode));
+        }
 
-    fn create_mysql_uri(user: String, password: String, master: String, slave: String, dbname: String) -> DbConf {
-        if user.is_empty() || password.is_empty() || master.is_empty() || slave.is_empty() || dbname.is_empty() {
-            return DbConf { master: String::new(), slave: String::new() };
+        let overlay = table.remove(&mode);
+        for m in MODES {
+            table.remove(m);
+        }
+        match overlay {
+            Some(Value::Table(section)) => merge_table(&mut table, section),
+            Some(_) => problems.push(format!("[{}] must be a table", mode)),
+            None => {}
+        }
+
+        // (variable, key, value)
+        let mut overrides: Vec<(String, String, String)> = Vec::new();
+        if let Ok(v) = std::env::var("LOG_DIR") {
+            overrides.push(("LOG_DIR".into(), "log".into(), v));
+        }
+        if let Ok(v) = std::env::var("KS_PORT") {
+            overrides.push(("KS_PORT".into(), "port".into(), v));
         }
+        let mut vars: Vec<(String, String, String)> = std::env::vars()
+            .filter_map(|(k, v)| {
+                let key = k.strip_prefix(ENV_PREFIX)?;
+                if key == "MODE" || key == "CONFIG" {
+                    return None;
+                }
+                let key = key.to_lowercase();
+                Some((k, key, v))
+            })
+            .collect();
+        vars.sort();
+        overrides.extend(vars);
 
-        return DbConf {
-            master: format!("mysql://{}:{}@{}/{}", user, password, master, dbname),
-            slave: format!("mysql://{}:{}@{}/{}", user, password, slave, dbname),
+        // Dotted key → the variable that set it, to point at it in problems.
+        let mut sources = HashMap::new();
+        for (var, key, value) in overrides {
+            let path: Vec<&str> = key.split("__").collect();
+            if let Err(e) = set_path(&mut table, &path, value) {
+                problems.push(format!("{} ({}): {}", path.join("."), var, e));
+            }
+            sources.insert(path.join("."), var);
+        }
+
+        // Deserializing stops at the first error, so drop the offending key
+        // and go again until the rest fits; `build` then checks what is left.
+        let mut dropped = Vec::new();
+        let raw: RawConfig = loop {
+            match serde_path_to_error::deserialize(Value::Table(table.clone())) {
+                Ok(raw) => break raw,
+                Err(e) => {
+                    let key = remove_path(&mut table, e.path());
+                    let source = key.as_ref().and_then(|k| sources.get(k)).map(|v| format!(" ({})", v));
+                    problems.push(format!("{}{}: {}", e.path(), source.unwrap_or_default(), e.inner().message()));
+                    match key {
+                        Some(key) => dropped.push(format!("{} is missing", key)),
+                        None => break RawConfig::default(),
+                    }
+                }
+            }
         };
+
+        // A dropped key is already reported; it is not missing as well.
+        Self::build(raw, mode, problems).map_err(|e| match e {
+            ConfigError::Invalid(mut problems) => {
+                problems.retain(|p| !dropped.contains(p));
+                ConfigError::Invalid(problems)
+            }
+            e => e,
+        })
     }
 
-    fn create_redis_uri(path: String) -> String {
-        if path.is_empty() {
-            return String::new();
+    // `problems` carries those found while loading, so that they are reported
+    // together with the ones found here.
+    fn build(raw: RawConfig, mode: String, mut problems: Vec<String>) -> Result<Self, ConfigError> {
+        let log = required(raw.log, "log", &mut problems);
+        let port = required(raw.port, "port", &mut problems);
+        let clio = ClioProvider::new(raw.clio.binary);
+
+        let mut mysql = HashMap::new();
+        for (name, c) in raw.mysql {
+            let scope = format!("mysql.{}", name);
+            let conf = ConfMySQL {
+                master: required(
+                    clio.host(c.clio_master.as_deref(), c.master),
+                    &format!("{}.master", scope),
+                    &mut problems,
+                ),
+                slaves: required_list(
+                    clio.all_hosts(c.clio_slaves.as_deref(), c.slaves),
+                    &format!("{}.slaves", scope),
+                    &mut problems,
+                ),
+                username: required(c.username, &format!("{}.username", scope), &mut problems),
+                password: c.password.unwrap_or_default(),
+                database: required(c.database, &format!("{}.database", scope), &mut problems),
+                balance: c.balance,
+                pool: c.pool.or(PoolConf::MYSQL),
+            };
+            conf.pool.validate(&format!("{}.pool", scope), &mut problems);
+            mysql.insert(name, conf);
+        }
+
+        let mut redis = HashMap::new();
+        for (name, c) in raw.redis {
+            let scope = format!("redis.{}", name);
+            let conf = ConfRedis {
+                host: required(clio.host(c.clio_host.as_deref(), c.host), &format!("{}.host", scope), &mut problems),
+                password: c.password.unwrap_or_default(),
+                pool: c.pool.or(PoolConf::REDIS),
+            };
+            conf.pool.validate(&format!("{}.pool", scope), &mut problems);
+            if !conf.pool.test_on_acquire {
+                problems.push(format!("{}.pool.test_on_acquire cannot be disabled for Redis", scope));
+            }
+            redis.insert(name, conf);
+        }
+
+        let batch = BatchConf { get_max: raw.batch.get_max.unwrap_or(1000), set_max: raw.batch.set_max.unwrap_or(100) };
+        if batch.get_max == 0 || batch.set_max == 0 {
+            problems.push("batch.get_max and batch.set_max must be greater than 0".to_string());
+        }
+
+        let defaults = RetryConf::default();
+        let retry = RetryConf {
+            max_attempts: raw.fetch.retry.max_attempts.unwrap_or(defaults.max_attempts),
+            base_backoff: raw.fetch.retry.base_backoff.unwrap_or(defaults.base_backoff),
+            max_backoff: raw.fetch.retry.max_backoff.unwrap_or(defaults.max_backoff),
+            jitter: raw.fetch.retry.jitter.unwrap_or(defaults.jitter),
+            retry_codes: raw.fetch.retry.retry_codes.unwrap_or(defaults.retry_codes),
+            non_idempotent: false,
+        };
+        if retry.max_attempts == 0 {
+            problems.push("fetch.retry.max_attempts must be greater than 0".to_string());
+        }
+        if retry.base_backoff > retry.max_backoff {
+            problems.push("fetch.retry.base_backoff exceeds max_backoff".to_string());
+        }
+        if !(0.0..=1.0).contains(&retry.jitter) {
+            problems.push("fetch.retry.jitter must be between 0 and 1".to_string());
+        }
+
+        let defaults = BreakerConf::default();
+        let breaker = BreakerConf {
+            failure_rate: raw.fetch.breaker.failure_rate.unwrap_or(defaults.failure_rate),
+            window: raw.fetch.breaker.window.unwrap_or(defaults.window),
+            min_requests: raw.fetch.breaker.min_requests.unwrap_or(defaults.min_requests),
+            cooldown: raw.fetch.breaker.cooldown.unwrap_or(defaults.cooldown),
+            half_open_probes: raw.fetch.breaker.half_open_probes.unwrap_or(defaults.half_open_probes),
+        };
+        if !(breaker.failure_rate > 0.0 && breaker.failure_rate <= 1.0) {
+            problems.push("fetch.breaker.failure_rate must be greater than 0 and at most 1".to_string());
+        }
+        if breaker.window == 0 || breaker.min_requests == 0 || breaker.cooldown == 0 || breaker.half_open_probes == 0 {
+            problems.push(
+                "fetch.breaker.window, min_requests, cooldown and half_open_probes must be greater than 0".to_string(),
+            );
+        }
+
+        if mysql.is_empty() {
+            problems.push("no [mysql.<name>] cluster configured".to_string());
+        }
+        if redis.is_empty() {
+            problems.push("no [redis.<name>] instance configured".to_string());
+        }
+
+        if !problems.is_empty() {
+            problems.sort();
+            return Err(ConfigError::Invalid(problems));
         }
 
-        format!("redis://{}", path)
+        Ok(Self {
+            cache: redis
+                .into_iter()
+                .map(|(name, c)| {
+                    (name, RedisConf { url: Self::create_redis_uri_with_password(&c.host, &c.password), pool: c.pool })
+                })
+                .collect(),
+            db: mysql
+                .into_iter()
+                .map(|(name, c)| {
+                    let uri = |host: &str| Self::create_mysql_uri(&c.username, &c.password, host, &c.database);
+                    let slaves = c.slaves.iter().map(|h| uri(h)).collect();
+                    (name, DbConf { master: uri(&c.master), slaves, balance: c.balance, pool: c.pool })
+                })
+                .collect(),
+            env: mode,
+            log,
+            port,
+            reload_interval: raw.reload_interval.unwrap_or(0),
+            health_interval: raw.health_interval.unwrap_or(5),
+            degraded_start: raw.degraded_start.unwrap_or(false),
+            shutdown_timeout: raw.shutdown_timeout.unwrap_or(30),
+            settings_store: raw.settings_store,
+            settings_cache_write: raw.settings_cache_write,
+            batch,
+            migrate_on_start: raw.migrate_on_start.unwrap_or(false),
+            fetch: FetchConf { retry, breaker },
+        })
     }
 
-    #[allow(dead_code)]
-    fn create_redis_uri_with_password(path: &str, password: &str) -> String {
-        if path.is_empty() {
-            return String::new();
+    // Unset means "test"; any other value is checked by `load`.
+    fn get_mode() -> String {
+        std::env::var("RP_MODE").unwrap_or_else(|_| "test".to_string())
+    }
+
+    fn create_mysql_uri(user: &str, password: &str, host: &str, dbname: &str) -> String {
+        match password {
+            "" => format!("mysql://{}@{}/{}", user, host, dbname),
+            _ => format!("mysql://{}:{}@{}/{}", user, password, host, dbname),
         }
+    }
 
+    fn create_redis_uri_with_password(path: &str, password: &str) -> String {
         match password {
             "" => format!("redis://{}", path),
             _ => format!("redis://:{}@{}", password, path),
         }
     }
+}
 
-    fn get_mysql_config() -> HashMap<String, ConfMySQL> {
-        let mode = Self::get_mode();
-        let key = format!("default-{}", mode);
-
-        let mut result = HashMap::new();
-        result.insert(
-            "default".to_string(),
-            match key.as_str() {
-                "default-release" => ConfMySQL {
-                    master: "127.0.0.1",
-                    slave: "127.0.0.1",
-                    username: "test",
-                    password: "test@123",
-                    database: "test",
-                },
-                _ => ConfMySQL {
-                    master: "127.0.0.1",
-                    slave: "127.0.0.1",
-                    username: "test",
-                    password: "test@123",
-                    database: "test",
-                },
-            },
-        );
-        result
+fn required(value: Option<String>, key: &str, problems: &mut Vec<String>) -> String {
+    match value {
+        Some(v) if !v.trim().is_empty() => v,
+        Some(_) => {
+            problems.push(format!("{} is empty", key));
+            String::new()
+        }
+        None => {
+            problems.push(format!("{} is missing", key));
+            String::new()
+        }
+    }
+}
+
+fn required_list(value: Option<Vec<String>>, key: &str, problems: &mut Vec<String>) -> Vec<String> {
+    match value {
+        Some(v) if v.is_empty() => {
+            problems.push(format!("{} is empty", key));
+            v
+        }
+        Some(v) => {
+            if v.iter().any(|h| h.trim().is_empty()) {
+                problems.push(format!("{} contains an empty host", key));
+            }
+            v
+        }
+        None => {
+            problems.push(format!("{} is missing", key));
+            Vec::new()
+        }
+    }
+}
+
+fn merge_table(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(b)), Value::Table(o)) => merge_table(b, o),
+            (_, v) => {
+                base.insert(key, v);
+            }
+        }
     }
+}
+
+// Sets the key at `path`, typed like the value it replaces. A value of
+// another type is a problem; keys not in the file stay strings and are
+// checked when the config is deserialized.
+fn set_path(table: &mut Table, path: &[&str], raw: String) -> Result<(), String> {
+    let Some((last, parents)) = path.split_last() else {
+        return Ok(());
+    };
 
-    fn get_redis_config() -> HashMap<String, ConfRedis> {
-        let mode = Self::get_mode();
-        let key = format!("default-{}", mode);
+    let mut current = table;
+    for (i, key) in parents.iter().enumerate() {
+        let entry = current.entry(key.to_string()).or_insert_with(|| Value::Table(Table::new()));
+        let Some(next) = entry.as_table_mut() else {
+            return Err(format!("{} is not a table", parents[..=i].join(".")));
+        };
+        current = next;
+    }
+
+    let expected = |what: &str| format!("expected {}, got {:?}", what, raw);
+    let value = match current.get(*last) {
+        Some(Value::Integer(_)) => raw.parse().map(Value::Integer).map_err(|_| expected("an integer"))?,
+        Some(Value::Float(_)) => raw.parse().map(Value::Float).map_err(|_| expected("a number"))?,
+        Some(Value::Boolean(_)) => raw.parse().map(Value::Boolean).map_err(|_| expected("true or false"))?,
+        Some(Value::Array(_)) => Value::Array(raw.split(',').map(|v| Value::String(v.trim().to_string())).collect()),
+        Some(Value::Table(_)) => return Err(expected("a table")),
+        _ => Value::String(raw),
+    };
+    current.insert(last.to_string(), value);
+    Ok(())
+}
 
-        let mut result = HashMap::new();
-        result.insert(
-            "default".to_string(),
-            match key.as_str() {
-                "default-release" => ConfRedis { host: "127.0.0.1", password: "" },
-                _ => ConfRedis { host: "127.0.0.1", password: "" },
-            },
+// Removes the key a deserialize error points at, or the key holding the
+// list element it points at. Returns the dotted key, None if there is none.
+fn remove_path(table: &mut Table, path: &serde_path_to_error::Path) -> Option<String> {
+    let keys: Vec<&str> = path
+        .iter()
+        .map_while(|segment| match segment {
+            serde_path_to_error::Segment::Map { key } => Some(key.as_str()),
+            _ => None,
+        })
+        .collect();
+    let (last, parents) = keys.split_last()?;
+
+    let mut current = table;
+    for key in parents {
+        current = current.get_mut(*key)?.as_table_mut()?;
+    }
+    current.remove(*last)?;
+    Some(keys.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        toml::from_str("port = \"8887\"\nhealth_interval = 5\n[batch]\nget_max = 1000\n").unwrap()
+    }
+
+    #[test]
+    fn set_path_keeps_the_type_of_the_replaced_value() {
+        let mut table = table();
+        set_path(&mut table, &["health_interval"], "10".to_string()).unwrap();
+        set_path(&mut table, &["port"], "8080".to_string()).unwrap();
+
+        assert_eq!(table["health_interval"], Value::Integer(10));
+        assert_eq!(table["port"], Value::String("8080".to_string()));
+    }
+
+    #[test]
+    fn set_path_reports_mistyped_values() {
+        let mut table = table();
+
+        assert_eq!(
+            set_path(&mut table, &["batch", "get_max"], "x".to_string()),
+            Err("expected an integer, got \"x\"".to_string())
         );
-        result
+        assert_eq!(set_path(&mut table, &["port", "x"], "1".to_string()), Err("port is not a table".to_string()));
+        assert_eq!(table["batch"]["get_max"], Value::Integer(1000));
+    }
+
+    #[test]
+    fn load_reports_every_problem_at_once() {
+        let path = std::env::temp_dir().join(format!("rust-practice-config-{}.toml", std::process::id()));
+        let text = r#"
+log = "logs"
+port = "8887"
+health_interval = "x"
+helth_interval = 5
+
+[mysql.relation]
+master = "127.0.0.1:3306"
+slaves = ["127.0.0.1:3306"]
+username = "root"
+pasword = "secret"
+
+[redis.default]
+host = 6379
+"#;
+        fs::write(&path, text).unwrap();
+
+        let result = Config::load(path.clone(), "prod".to_string());
+        fs::remove_file(path).unwrap();
+
+        let Err(ConfigError::Invalid(problems)) = result else { panic!("expected problems") };
+        let found = |start: &str| problems.iter().any(|p| p.starts_with(start));
+        assert!(found("RP_MODE must be one of release, test, got \"prod\""));
+        assert!(found("health_interval: invalid type"));
+        assert!(found("helth_interval: unknown field"));
+        assert!(found("mysql.relation.pasword: unknown field"));
+        assert!(found("mysql.relation.database is missing"));
+        // Reported as mistyped, not as missing too.
+        assert!(found("redis.default.host: invalid type"));
+        assert_eq!(problems.len(), 6, "{:#?}", problems);
     }
 }
@@ -0,0 +1,13 @@
+// src/handler/admin.rs
+use crate::{
+    model::domain::AppState,
+    utils::response::{AppResult, Success},
+};
+use axum::extract::State;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+// Circuit breaker state of every upstream host `Fetch` has called.
+pub async fn circuits(State(state): State<Arc<AppState>>) -> AppResult<Value> {
+    Ok(Success(json!({ "circuits": state.fetch.breaker().circuits() })))
+}
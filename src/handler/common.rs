@@ -1,14 +1,17 @@
 // src/handler/common.rs
 use crate::{
-    model::domain::AppState,
-    utils::response::{AppError, AppResult, Code, SafeJson, Success},
+    model::{
+        domain::AppState,
+        dto::{SetSettingsRequest, SettingsResponse},
+    },
+    utils::response::{AppError, AppResult, Code, IfMatch, Success, ValidJson, etag},
 };
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
+    http::{StatusCode, header},
     response::IntoResponse,
 };
-use serde_json::{Value, json};
+use serde_json::Value;
 use std::sync::Arc;
 
 pub async fn ok() -> String {
@@ -20,41 +23,32 @@ pub async fn not_found() -> impl IntoResponse {
 }
 
 // test
-pub async fn get_something(State(state): State<Arc<AppState>>, Path(uid): Path<u64>) -> AppResult<Value> {
+pub async fn get_something(
+    State(state): State<Arc<AppState>>,
+    Path(uid): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
     if uid == 0 {
         return Err(AppError::Logic(Code::UnprocessableEntity));
     }
 
-    let data = if state.env == "test" {
-        state.repository.db.get_test(uid).await.unwrap_or(json!({}))
-    } else {
-        state.repository.cache.get_test(uid).await.unwrap_or(json!({}))
-    };
-    // println!("LocalCache -> {}", data);
+    state.settings.check()?;
+    let doc = state.settings.get(uid).await?;
 
-    Ok(Success(data))
+    Ok(([(header::ETAG, etag(doc.version))], Success(SettingsResponse::from(doc.content))))
 }
 
 pub async fn set_something(
     State(state): State<Arc<AppState>>,
     Path(uid): Path<u64>,
-    SafeJson(payload): SafeJson<Value>,
+    IfMatch(expect): IfMatch,
+    ValidJson(payload): ValidJson<SetSettingsRequest>,
 ) -> AppResult<Value> {
-    if uid <= 0 {
+    if uid == 0 {
         return Err(AppError::Logic(Code::UnprocessableEntity));
     }
 
-    if let Value::Object(obj) = &payload {
-        if obj.is_empty() {
-            return Err(AppError::Logic(Code::UnprocessableEntity));
-        }
-    }
-
-    if state.env == "test" {
-        let _ = state.repository.db.add_test(uid, payload).await;
-    } else {
-        let _ = state.repository.cache.add_test(uid, payload).await;
-    }
+    state.settings.check()?;
+    state.settings.set(uid, payload.fields, expect).await?;
 
     Ok(Success::empty())
 }
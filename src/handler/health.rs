@@ -0,0 +1,94 @@
+// src/handler/health.rs
+use crate::{
+    model::domain::AppState,
+    utils::{
+        prometheus::HEALTH_CHECK_DURATION,
+        response::{AppError, AppResult, Code, Success},
+    },
+};
+use axum::extract::State;
+use serde_json::{Map, Value, json};
+use std::{future::Future, sync::Arc};
+use tokio::{
+    task::JoinSet,
+    time::{Duration, Instant, timeout},
+};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub async fn live() -> AppResult<Value> {
+    Ok(Success(json!({ "status": "alive" })))
+}
+
+// Masters and Redis instances are required; replicas are reported but only
+// degrade reads to the master when they fail.
+pub async fn ready(State(state): State<Arc<AppState>>) -> AppResult<Value> {
+    let mut checks = JoinSet::new();
+
+    for (name, manager) in state.repository.db.client().clusters.iter() {
+        let pool = manager.master.clone();
+        checks.spawn(probe(format!("mysql.{}.master", name), true, async move {
+            sqlx::query("SELECT 1").execute(&pool).await.map(|_| ()).map_err(|e| e.to_string())
+        }));
+
+        for replica in manager.replicas.replicas() {
+            let pool = replica.pool.clone();
+            checks.spawn(probe(format!("mysql.{}.replica.{}", name, replica.host), false, async move {
+                sqlx::query("SELECT 1").execute(&pool).await.map(|_| ()).map_err(|e| e.to_string())
+            }));
+        }
+    }
+
+    for (name, manager) in state.repository.cache.client().instances.iter() {
+        let pool = manager.pool.clone();
+        checks.spawn(probe(format!("redis.{}", name), true, async move {
+            let mut conn = pool.get().await.map_err(|e| e.to_string())?;
+            deadpool_redis::redis::cmd("PING")
+                .query_async::<String>(&mut conn)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }));
+    }
+
+    let mut ready = true;
+    let mut results = Map::new();
+    for (name, required, result) in checks.join_all().await {
+        ready &= !required || result["status"] == "up";
+        results.insert(name, result);
+    }
+
+    let data = json!({ "status": if ready { "ready" } else { "not_ready" }, "checks": results });
+    if !ready {
+        return Err(AppError::Detail(Code::ServiceUnavailable, data));
+    }
+
+    Ok(Success(data))
+}
+
+async fn probe<F>(name: String, required: bool, check: F) -> (String, bool, Value)
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let start = Instant::now();
+    let result = match timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(format!("timed out after {:?}", CHECK_TIMEOUT)),
+    };
+    let elapsed = start.elapsed();
+
+    let outcome = if result.is_ok() { "up" } else { "down" };
+    HEALTH_CHECK_DURATION.with_label_values(&[name.as_str(), outcome]).observe(elapsed.as_secs_f64());
+
+    let mut value = json!({
+        "status": outcome,
+        "required": required,
+        "latency_ms": elapsed.as_secs_f64() * 1000.0,
+    });
+    if let Err(e) = result {
+        value["error"] = Value::String(e);
+    }
+
+    (name, required, value)
+}
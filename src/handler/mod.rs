@@ -1 +1,4 @@
+pub mod admin;
 pub mod common;
+pub mod health;
+pub mod settings;
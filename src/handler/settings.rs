@@ -0,0 +1,171 @@
+// src/handler/settings.rs
+use crate::{
+    model::{
+        domain::AppState,
+        dto::{
+            BatchGetRequest, BatchGetResult, BatchResponse, BatchSetRequest, BatchSetResult, PatchSettingsRequest,
+            ReplaceSettingsRequest, SettingsResponse,
+        },
+    },
+    repository::{Expect, RepoError, json_path},
+    utils::{
+        response::{AppError, AppResult, Code, IfMatch, Success, ValidJson, etag},
+        validate::{self, FieldError, Validator},
+    },
+};
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use serde_json::Value;
+use std::sync::Arc;
+
+pub async fn get(State(state): State<Arc<AppState>>, Path(uid): Path<u64>) -> Result<impl IntoResponse, AppError> {
+    check_uid(uid)?;
+
+    state.settings.check()?;
+    let doc = state.settings.get(uid).await?;
+
+    Ok(([(header::ETAG, etag(doc.version))], Success(SettingsResponse::from(doc.content))))
+}
+
+pub async fn get_field(State(state): State<Arc<AppState>>, Path((uid, key)): Path<(u64, String)>) -> AppResult<Value> {
+    check_uid(uid)?;
+    check_key(&key)?;
+
+    state.settings.check()?;
+    match state.settings.get_field(uid, &key).await? {
+        Some(value) => Ok(Success(value)),
+        None => Err(AppError::Logic(Code::NotFound)),
+    }
+}
+
+pub async fn patch(
+    State(state): State<Arc<AppState>>,
+    Path(uid): Path<u64>,
+    IfMatch(expect): IfMatch,
+    ValidJson(payload): ValidJson<PatchSettingsRequest>,
+) -> AppResult<Value> {
+    check_uid(uid)?;
+
+    state.settings.check()?;
+    state.settings.patch(uid, payload.patch, expect).await?;
+
+    Ok(Success::empty())
+}
+
+pub async fn replace(
+    State(state): State<Arc<AppState>>,
+    Path(uid): Path<u64>,
+    IfMatch(expect): IfMatch,
+    ValidJson(payload): ValidJson<ReplaceSettingsRequest>,
+) -> AppResult<Value> {
+    check_uid(uid)?;
+
+    state.settings.check()?;
+    state.settings.replace(uid, payload.content, expect).await?;
+
+    Ok(Success::empty())
+}
+
+pub async fn delete(
+    State(state): State<Arc<AppState>>,
+    Path(uid): Path<u64>,
+    IfMatch(expect): IfMatch,
+) -> AppResult<Value> {
+    check_uid(uid)?;
+
+    state.settings.check()?;
+    state.settings.delete(uid, expect).await?;
+
+    Ok(Success::empty())
+}
+
+pub async fn delete_field(
+    State(state): State<Arc<AppState>>,
+    Path((uid, key)): Path<(u64, String)>,
+    IfMatch(expect): IfMatch,
+) -> AppResult<Value> {
+    check_uid(uid)?;
+    check_key(&key)?;
+
+    state.settings.check()?;
+    state.settings.delete_field(uid, &key, expect).await?;
+
+    Ok(Success::empty())
+}
+
+pub async fn batch_get(
+    State(state): State<Arc<AppState>>,
+    ValidJson(payload): ValidJson<BatchGetRequest>,
+) -> AppResult<BatchResponse<BatchGetResult>> {
+    check_batch("uids", payload.uids.len(), state.batch.get_max)?;
+
+    state.settings.check()?;
+    let results = state.settings.get_many(&payload.uids).await;
+    let results = payload
+        .uids
+        .into_iter()
+        .zip(results)
+        .map(|(uid, result)| match result {
+            Ok(doc) => BatchGetResult { uid, settings: Some(doc.content), version: Some(doc.version), error: None },
+            Err(e) => BatchGetResult { uid, settings: None, version: None, error: Some(batch_error(uid, e)) },
+        })
+        .collect();
+
+    Ok(Success(BatchResponse { results }))
+}
+
+pub async fn batch_set(
+    State(state): State<Arc<AppState>>,
+    ValidJson(payload): ValidJson<BatchSetRequest>,
+) -> AppResult<BatchResponse<BatchSetResult>> {
+    check_batch("items", payload.items.len(), state.batch.set_max)?;
+
+    state.settings.check()?;
+    let uids: Vec<u64> = payload.items.iter().map(|item| item.uid).collect();
+    let items = payload
+        .items
+        .into_iter()
+        .map(|item| (item.uid, item.fields, item.version.map_or(Expect::Any, Expect::Version)))
+        .collect();
+    let results = state.settings.set_many(items).await;
+    let results = uids
+        .into_iter()
+        .zip(results)
+        .map(|(uid, result)| BatchSetResult { uid, error: result.err().map(|e| batch_error(uid, e)) })
+        .collect();
+
+    Ok(Success(BatchResponse { results }))
+}
+
+// The batch limits come from the config, so they are checked here rather
+// than by the DTOs.
+fn check_batch(field: &str, len: usize, max: usize) -> Result<(), AppError> {
+    let mut v = Validator::new();
+    v.range(field, len, 1, max);
+    v.finish().map_err(validate::rejection)
+}
+
+fn check_uid(uid: u64) -> Result<(), AppError> {
+    if uid == 0 {
+        return Err(AppError::Logic(Code::UnprocessableEntity));
+    }
+    Ok(())
+}
+
+fn check_key(key: &str) -> Result<(), AppError> {
+    json_path::member(key)
+        .map(|_| ())
+        .map_err(|e| validate::rejection(vec![FieldError { field: key.to_string(), reason: e.to_string() }]))
+}
+
+// The message of the code the error maps to; only backend failures are logged.
+fn batch_error(uid: u64, e: RepoError) -> String {
+    let code = e.code();
+    if code.http_status().is_server_error() {
+        tracing::error!("Settings batch operation failed for uid {}: {}", uid, e);
+    }
+    code.message().to_string()
+}
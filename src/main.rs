@@ -9,36 +9,156 @@ mod utils;
 use axum::serve;
 use config::Config;
 use model::domain::AppState;
-use repository::Repository;
+use repository::{Repository, SETTINGS_CLUSTER, migrate, replica, settings};
 use std::{net::SocketAddr, sync::Arc};
-use tokio::net::TcpListener;
-use utils::{connect::Connect, fetch::Fetch, log, prometheus};
+use tokio::{net::TcpListener, time::Duration};
+use tokio_util::sync::CancellationToken;
+use utils::{connect::Connect, fetch::Fetch, log, prometheus, reload, shutdown};
 
 #[tokio::main]
 async fn main() {
-    let cfg = Config::init();
+    let cfg = Config::init().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    // `rust-practice migrate up|status` runs against the master and exits
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        std::process::exit(run_migrate(&cfg, args.get(1).map(String::as_str)).await);
+    }
 
     // Log
-    let _ = log::init(cfg.log.clone());
+    log::init(cfg.log.clone());
+    // Cancels every background task on shutdown
+    let token = CancellationToken::new();
     // Connect to database
-    let (db, cache) = Connect::new(cfg.clone()).await;
+    let (db, cache) = Connect::new(cfg.clone(), token.clone()).await.unwrap_or_else(|e| {
+        tracing::error!("{}", e);
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
     // Create state
+    let repository = Repository::new(cache, db);
+    if cfg.migrate_on_start {
+        migrate_on_start(&repository, cfg.degraded_start).await;
+    }
     let state = Arc::new(AppState {
         env: cfg.env.clone(),
-        fetch: Fetch::new(),
+        fetch: Fetch::new(cfg.fetch.clone()),
         prometheus: prometheus::new(),
-        repository: Repository::new(cache, db),
+        settings: settings::new(cfg.settings_store, cfg.settings_cache_write, &repository, token.clone()),
+        batch: cfg.batch,
+        repository,
     });
     println!("→ Starting application in the {} environment", cfg.env.clone());
 
     // Metrics record uptime
-    prometheus::start_record_uptime();
+    prometheus::start_record_uptime(token.clone());
+    // Eject failing read replicas
+    replica::start_health_check(state.repository.db.clone(), cfg.health_interval, token.clone());
+    // Watch for changed MySQL / Redis endpoints
+    reload::start_watcher(state.clone(), cfg.clone(), token.clone());
 
     // The main thread starts the HTTP service
-    let app = router::init(state).await;
+    let app = router::init(state.clone()).await;
     let addr: SocketAddr = format!("0.0.0.0:{}", cfg.port.clone()).parse().expect("Invalid server address");
     let listener = TcpListener::bind(addr).await.expect("Failed to bind server");
     println!("→ Application started successfully. Listening on http://{}", addr);
 
-    serve(listener, app).await.expect("Service crashed");
+    let signal = token.clone();
+    let server = serve(listener, app).with_graceful_shutdown(async move {
+        shutdown::signal().await;
+        signal.cancel();
+    });
+    let drain = async {
+        token.cancelled().await;
+        tokio::time::sleep(Duration::from_secs(cfg.shutdown_timeout)).await;
+    };
+
+    // Stop accepting connections, then give in-flight requests shutdown_timeout to finish
+    tokio::select! {
+        res = server => res.expect("Service crashed"),
+        _ = drain => tracing::warn!("In-flight requests did not finish within {}s, closing anyway", cfg.shutdown_timeout),
+    }
+
+    token.cancel();
+    let close = tokio::time::timeout(Duration::from_secs(cfg.shutdown_timeout), state.repository.close());
+    if close.await.is_err() {
+        tracing::warn!("Connection pools did not close within {}s", cfg.shutdown_timeout);
+    }
+
+    tracing::info!("Application stopped");
+    println!("→ Application stopped");
+}
+
+// A failed migration stops the startup, unless the master is merely
+// unreachable at a degraded start.
+async fn migrate_on_start(repository: &Repository, degraded: bool) {
+    let master = match repository.db.cluster(SETTINGS_CLUSTER) {
+        Ok(manager) => manager.master,
+        Err(e) if degraded => {
+            tracing::error!("Skipping migrations, {}", e);
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Cannot run migrations: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = migrate::up(&master).await {
+        tracing::error!("Migrations failed: {}", e);
+        eprintln!("Migrations failed: {}", e);
+        std::process::exit(1);
+    }
+    tracing::info!("Migrations are up to date");
+}
+
+async fn run_migrate(cfg: &Config, action: Option<&str>) -> i32 {
+    let Some(action @ ("up" | "status")) = action else {
+        eprintln!("Usage: rust-practice migrate <up|status>");
+        return 2;
+    };
+    let Some(conf) = cfg.db.get(SETTINGS_CLUSTER) else {
+        eprintln!("No [mysql.{}] cluster configured", SETTINGS_CLUSTER);
+        return 1;
+    };
+
+    let backend = format!("mysql.{} master", SETTINGS_CLUSTER);
+    let pool = match Connect::create_db_pool(&backend, &conf.master, &conf.pool).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let code = match action {
+        "up" => match migrate::up(&pool).await {
+            Ok(()) => {
+                println!("→ Migrations are up to date");
+                0
+            }
+            Err(e) => {
+                eprintln!("Migrations failed: {}", e);
+                1
+            }
+        },
+        _ => match migrate::status(&pool).await {
+            Ok(list) => {
+                for m in &list {
+                    println!("{:>6}  {:<8}  {}", m.version, m.state.to_string(), m.description);
+                }
+                0
+            }
+            Err(e) => {
+                eprintln!("Cannot read migration status: {}", e);
+                1
+            }
+        },
+    };
+
+    pool.close().await;
+    code
 }
@@ -1,11 +1,18 @@
 // src/model/domain.rs
 use crate::{
-    repository::Repository,
+    config::BatchConf,
+    repository::{Repository, replica::ReadPool, settings::SettingsStore},
     utils::{fetch::Fetch, prometheus::PromOpts},
 };
 use deadpool_redis::Pool as RedisPool;
 use sqlx::{MySql, Pool as MysqlPool};
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 #[allow(dead_code)]
 #[derive(Clone)]
@@ -14,23 +21,85 @@ pub struct AppState {
     pub fetch: Fetch,
     pub prometheus: Arc<PromOpts>,
     pub repository: Repository,
+    pub settings: Arc<dyn SettingsStore>,
+    pub batch: BatchConf,
 }
 
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct DbClient {
-    pub relation: DbManager,
+    pub clusters: HashMap<String, DbManager>,
+}
+
+impl DbClient {
+    // Waits for checked-out connections to be returned before closing them.
+    pub async fn close(&self) {
+        for manager in self.clusters.values() {
+            manager.master.close().await;
+            for replica in manager.replicas.replicas() {
+                replica.pool.close().await;
+            }
+        }
+    }
 }
 
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct DbManager {
     pub master: MysqlPool<MySql>,
-    pub slave: MysqlPool<MySql>,
+    pub replicas: ReadPool,
+    pub status: BackendStatus,
+}
+
+impl DbManager {
+    // A healthy replica picked by the cluster's balance strategy, or the
+    // master when every replica is down.
+    pub fn reader(&self) -> &MysqlPool<MySql> {
+        match self.replicas.select() {
+            Some(replica) => &replica.pool,
+            None => {
+                self.replicas.record_master_fallback();
+                &self.master
+            }
+        }
+    }
 }
 
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct CacheClient {
-    pub profile: RedisPool,
+    pub instances: HashMap<String, CacheManager>,
+}
+
+impl CacheClient {
+    pub fn close(&self) {
+        for manager in self.instances.values() {
+            manager.pool.close();
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CacheManager {
+    pub pool: RedisPool,
+    pub status: BackendStatus,
+}
+
+// Whether a backend could be reached. Starts down for backends that were
+// unreachable at a degraded startup and flips up once a retry connects.
+#[derive(Clone, Debug)]
+pub struct BackendStatus(Arc<AtomicBool>);
+
+impl BackendStatus {
+    pub fn new(up: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(up)))
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, up: bool) {
+        self.0.store(up, Ordering::Relaxed);
+    }
 }
@@ -1 +1,154 @@
 // src/model/dto.rs
+use crate::{
+    repository::json_path,
+    utils::validate::{ROOT, Validate, Validator},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+// Most fields a single settings write may carry.
+pub const MAX_SETTINGS_FIELDS: usize = 100;
+
+// Body of POST /set/{uid}/something: the fields to set, with any JSON value.
+#[derive(Deserialize, Debug)]
+#[serde(transparent)]
+pub struct SetSettingsRequest {
+    pub fields: Map<String, Value>,
+}
+
+impl Validate for SetSettingsRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.keys(ROOT, &self.fields, 1, MAX_SETTINGS_FIELDS);
+        check_keys(v, &self.fields);
+    }
+}
+
+// Body of PATCH /settings/{uid}: a JSON Merge Patch, where null removes a field.
+#[derive(Deserialize, Debug)]
+#[serde(transparent)]
+pub struct PatchSettingsRequest {
+    pub patch: Map<String, Value>,
+}
+
+impl Validate for PatchSettingsRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.keys(ROOT, &self.patch, 1, MAX_SETTINGS_FIELDS);
+        check_keys(v, &self.patch);
+    }
+}
+
+// Body of PUT /settings/{uid}: the whole new document, possibly empty.
+#[derive(Deserialize, Debug)]
+#[serde(transparent)]
+pub struct ReplaceSettingsRequest {
+    pub content: Map<String, Value>,
+}
+
+impl Validate for ReplaceSettingsRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.keys(ROOT, &self.content, 0, MAX_SETTINGS_FIELDS);
+        check_keys(v, &self.content);
+        for (key, value) in &self.content {
+            v.check(key, !value.is_null(), "must not be null");
+        }
+    }
+}
+
+fn check_keys(v: &mut Validator, fields: &Map<String, Value>) {
+    for key in fields.keys() {
+        if let Err(e) = json_path::member(key) {
+            v.check(key, false, e);
+        }
+    }
+}
+
+// Data of GET /get/{uid}/something and GET /settings/{uid}, serialized as the bare settings object.
+#[derive(Serialize, Debug)]
+#[serde(transparent)]
+pub struct SettingsResponse {
+    pub fields: Map<String, Value>,
+}
+
+impl From<Map<String, Value>> for SettingsResponse {
+    fn from(fields: Map<String, Value>) -> Self {
+        Self { fields }
+    }
+}
+
+// Body of POST /settings/batch/get.
+#[derive(Deserialize, Debug)]
+pub struct BatchGetRequest {
+    pub uids: Vec<u64>,
+}
+
+impl Validate for BatchGetRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.check("uids", !self.uids.is_empty(), "must not be empty");
+        check_uids(v, self.uids.iter().enumerate().map(|(i, uid)| (format!("uids[{}]", i), *uid)));
+    }
+}
+
+// Body of POST /settings/batch/set: fields to set per user, each with an
+// optional version that must match like If-Match.
+#[derive(Deserialize, Debug)]
+pub struct BatchSetRequest {
+    pub items: Vec<BatchSetItem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BatchSetItem {
+    pub uid: u64,
+    pub fields: Map<String, Value>,
+    pub version: Option<u64>,
+}
+
+impl Validate for BatchSetRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.check("items", !self.items.is_empty(), "must not be empty");
+        check_uids(v, self.items.iter().enumerate().map(|(i, item)| (format!("items[{}].uid", i), item.uid)));
+        for (i, item) in self.items.iter().enumerate() {
+            let field = format!("items[{}].fields", i);
+            v.keys(&field, &item.fields, 1, MAX_SETTINGS_FIELDS);
+            for key in item.fields.keys() {
+                if let Err(e) = json_path::member(key) {
+                    v.check(&format!("{}.{}", field, key), false, e);
+                }
+            }
+        }
+    }
+}
+
+// Data of the batch endpoints: one result per requested uid, in order.
+#[derive(Serialize, Debug)]
+pub struct BatchResponse<T> {
+    pub results: Vec<T>,
+}
+
+// `settings` and `version` on success, `error` when this uid failed.
+#[derive(Serialize, Debug)]
+pub struct BatchGetResult {
+    pub uid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Map<String, Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchSetResult {
+    pub uid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Every uid must be non-zero and appear once.
+fn check_uids(v: &mut Validator, uids: impl Iterator<Item = (String, u64)>) {
+    let mut seen = HashSet::new();
+    for (field, uid) in uids {
+        v.check(&field, uid != 0, "must be greater than 0");
+        v.check(&field, seen.insert(uid), "appears more than once");
+    }
+}
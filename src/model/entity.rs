@@ -1 +1,28 @@
 // src/model/entity.rs
+use serde_json::{Map, Value};
+use sqlx::{FromRow, types::Json};
+
+// A row of the `settings` table. `content` is a JSON object whose values keep
+// their JSON types; `version` goes up by one on every write.
+#[allow(dead_code)]
+#[derive(FromRow, Clone, Debug)]
+pub struct Settings {
+    pub uid: u64,
+    pub content: Json<Map<String, Value>>,
+    pub version: u64,
+    pub event_time: u64,
+}
+
+// A settings document as every store returns it. Version 0 means the user
+// has no document yet.
+#[derive(Clone, Debug, Default)]
+pub struct SettingsDoc {
+    pub content: Map<String, Value>,
+    pub version: u64,
+}
+
+impl From<Settings> for SettingsDoc {
+    fn from(row: Settings) -> Self {
+        Self { content: row.content.0, version: row.version }
+    }
+}
@@ -1,54 +1,454 @@
 // src/repository/cache.rs
-use crate::{model::domain::CacheClient, utils::common::hashmap_to_serde_map};
-use deadpool_redis::redis::AsyncCommands;
-use serde_json::Value;
-use std::collections::HashMap;
+use crate::{
+    model::{domain::CacheClient, entity::SettingsDoc},
+    repository::{Expect, LookupError, RepoError, SETTINGS_INSTANCE},
+    utils::common::{hash_to_serde_map, merge_patch, serde_map_to_hash},
+};
+use arc_swap::ArcSwap;
+use deadpool_redis::{
+    Connection, Pool,
+    redis::{AsyncCommands, Script, ScriptInvocation, pipe},
+};
+use serde_json::{Map, Value};
+use std::{collections::HashMap, sync::Arc};
+
+// Read-modify-write attempts for a patch that merges nested objects.
+const PATCH_ATTEMPTS: usize = 3;
+
+// Applies one write to a settings hash if its version matches, then bumps the
+// version. Returns the new version, 0 after a delete or -1 on a mismatch.
+//
+// KEYS[1] settings hash, KEYS[2] its version
+// ARGV[1] expected version, "*" for any existing document or "" for no check
+// ARGV[2] "merge", "replace" (clear the hash first) or "delete"
+// ARGV[3] number of fields to remove, then those fields, then field / value pairs to set
+const WRITE_SCRIPT: &str = r#"
+local version = tonumber(redis.call('GET', KEYS[2]) or '0')
+local expect = ARGV[1]
+if (expect == '*' and version == 0) or (expect ~= '' and expect ~= '*' and tonumber(expect) ~= version) then
+  return -1
+end
+if ARGV[2] == 'delete' then
+  redis.call('DEL', KEYS[1], KEYS[2])
+  return 0
+end
+if ARGV[2] == 'replace' then
+  redis.call('DEL', KEYS[1])
+end
+local removed = tonumber(ARGV[3])
+for i = 4, 3 + removed do
+  redis.call('HDEL', KEYS[1], ARGV[i])
+end
+for i = 4 + removed, #ARGV, 2 do
+  redis.call('HSET', KEYS[1], ARGV[i], ARGV[i + 1])
+end
+return redis.call('INCR', KEYS[2])
+"#;
+
+// Caches a copy read from MySQL unless Redis already holds the same or a
+// newer version, or the version key changed since the miss: invalidation
+// leaves a tombstone there, so a reader that loaded its copy before a write
+// cannot cache it after the write dropped the old one. Returns 1 when the copy
+// was stored.
+//
+// KEYS[1] settings hash, KEYS[2] its version
+// ARGV[1] version of the copy, ARGV[2] TTL in seconds, ARGV[3] the version key
+// as seen on the miss ("" if absent), then field / value pairs
+const FILL_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[2]) or ''
+local cached = tonumber(current)
+if cached then
+  if cached >= tonumber(ARGV[1]) then
+    return 0
+  end
+elseif current ~= ARGV[3] then
+  return 0
+end
+redis.call('DEL', KEYS[1])
+for i = 4, #ARGV, 2 do
+  redis.call('HSET', KEYS[1], ARGV[i], ARGV[i + 1])
+end
+if #ARGV > 3 then
+  redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+redis.call('SET', KEYS[2], ARGV[1], 'EX', ARGV[2])
+return 1
+"#;
+
+// Lifetime of a copy cached from MySQL, and of the tombstone left by an
+// invalidation. Writes keep the cache in sync; this only bounds how long a
+// copy that missed an update can be served.
+const FILL_TTL: u64 = 600;
+
+// What the cache holds for a user: a copy carrying its MySQL version, or on a
+// miss the raw version key to hand back to `fill_test`.
+#[derive(Debug)]
+pub enum Cached {
+    Hit(SettingsDoc),
+    Miss(String),
+}
 
 #[derive(Clone, Debug)]
 pub struct Cache {
-    pub cache: CacheClient,
+    cache: Arc<ArcSwap<CacheClient>>,
 }
 
 impl Cache {
     pub fn new(cache: CacheClient) -> Self {
-        Self { cache }
+        Self { cache: Arc::new(ArcSwap::from_pointee(cache)) }
+    }
+
+    pub fn client(&self) -> Arc<CacheClient> {
+        self.cache.load_full()
+    }
+
+    pub fn swap(&self, cache: CacheClient) -> Arc<CacheClient> {
+        self.cache.swap(Arc::new(cache))
+    }
+
+    pub fn instance(&self, name: &str) -> Result<Pool, LookupError> {
+        let client = self.client();
+        let manager = client.instances.get(name).ok_or_else(|| LookupError::Instance(name.to_string()))?;
+        if !manager.status.is_up() {
+            return Err(LookupError::Unavailable(format!("redis.{}", name)));
+        }
+        Ok(manager.pool.clone())
     }
 
-    pub async fn get_test(&self, uid: u64) -> Result<Value, String> {
-        let mut conn = self.cache.profile.get().await.map_err(|e| format!("Redis pool error: {}", e))?;
+    async fn conn(&self) -> Result<Connection, RepoError> {
+        let pool = self.instance(SETTINGS_INSTANCE)?;
+        Ok(pool.get().await?)
+    }
+
+    pub async fn get_test(&self, uid: u64) -> Result<SettingsDoc, RepoError> {
+        let mut conn = self.conn().await?;
 
-        let key = format!("u:{}:setting", uid);
-        let data: HashMap<String, String> =
-            conn.hgetall(&key).await.map_err(|e| format!("Redis hgetall error: {}", e))?;
-        let result = hashmap_to_serde_map(data);
-        // println!("get_setting -> {:?}", result);
+        let (key, version_key) = keys(uid);
+        let (data, version): (HashMap<String, String>, Option<u64>) =
+            pipe().atomic().hgetall(&key).get(&version_key).query_async(&mut conn).await?;
 
-        Ok(Value::Object(result))
+        Ok(SettingsDoc { content: hash_to_serde_map(data), version: version.unwrap_or(0) })
     }
 
-    pub async fn add_test(&self, uid: u64, data: Value) -> Result<Value, String> {
-        let mut conn = self.cache.profile.get().await.map_err(|e| format!("Redis pool error: {}", e))?;
+    // Like `get_test`, for a cache in front of MySQL: copies without a version
+    // predate versioning and count as misses.
+    pub async fn lookup_test(&self, uid: u64) -> Result<Cached, RepoError> {
+        let mut conn = self.conn().await?;
+
+        let (key, version_key) = keys(uid);
+        let (data, version): (HashMap<String, String>, Option<String>) =
+            pipe().atomic().hgetall(&key).get(&version_key).query_async(&mut conn).await?;
+
+        Ok(cached(data, version))
+    }
+
+    pub async fn get_field_test(&self, uid: u64, field: &str) -> Result<Option<Value>, RepoError> {
+        let mut conn = self.conn().await?;
+
+        let (key, _) = keys(uid);
+        let data: Option<String> = conn.hget(&key, field).await?;
+
+        Ok(data.map(|v| serde_json::from_str(&v).unwrap_or(Value::String(v))))
+    }
+
+    // The write methods return Ok(false) when `expect` does not hold.
+    pub async fn add_test(&self, uid: u64, data: &Map<String, Value>, expect: Expect) -> Result<bool, RepoError> {
+        self.write(uid, expect, "merge", &[], data).await
+    }
+
+    // Applies a JSON Merge Patch: null fields are removed with HDEL, nested
+    // objects are merged into the stored value, everything else is HSET.
+    pub async fn patch_test(&self, uid: u64, patch: &Map<String, Value>, expect: Expect) -> Result<bool, RepoError> {
+        let removed: Vec<&String> = patch.iter().filter(|(_, v)| v.is_null()).map(|(k, _)| k).collect();
+        let nested: Vec<&String> = patch.iter().filter(|(_, v)| v.is_object()).map(|(k, _)| k).collect();
+        if nested.is_empty() {
+            let mut changed = Map::new();
+            merge_patch(&mut changed, patch);
+            return self.write(uid, expect, "merge", &removed, &changed).await;
+        }
 
-        let key = format!("u:{}:setting", uid);
-        let obj = match data.as_object() {
-            Some(map) => map,
-            None => return Ok(Value::Null),
-        };
+        // Read the nested fields, merge them here and write them back only if
+        // the document has not changed in between.
+        for _ in 0..PATCH_ATTEMPTS {
+            let mut conn = self.conn().await?;
+            let (key, version_key) = keys(uid);
+            let (version, values): (Option<u64>, Vec<Option<String>>) =
+                pipe().atomic().get(&version_key).cmd("HMGET").arg(&key).arg(&nested).query_async(&mut conn).await?;
+            drop(conn);
+
+            let version = version.unwrap_or(0);
+            match expect {
+                Expect::Exists if version == 0 => return Ok(false),
+                Expect::Version(v) if v != version => return Ok(false),
+                _ => {}
+            }
+
+            let stored = nested.iter().zip(values).filter_map(|(field, value)| Some((field.to_string(), value?)));
+            let mut changed = hash_to_serde_map(stored.collect());
+            merge_patch(&mut changed, patch);
+
+            if self.write(uid, Expect::Version(version), "merge", &removed, &changed).await? {
+                return Ok(true);
+            }
+            if let Expect::Version(_) = expect {
+                return Ok(false);
+            }
+        }
+
+        Err(RepoError::Other(format!("Redis patch gave up after {} concurrent changes", PATCH_ATTEMPTS)))
+    }
+
+    pub async fn replace_test(&self, uid: u64, data: &Map<String, Value>, expect: Expect) -> Result<bool, RepoError> {
+        self.write(uid, expect, "replace", &[], data).await
+    }
+
+    pub async fn delete_test(&self, uid: u64, expect: Expect) -> Result<bool, RepoError> {
+        self.write(uid, expect, "delete", &[], &Map::new()).await
+    }
+
+    // Drops the cached copy regardless of its version.
+    pub async fn invalidate_test(&self, uid: u64) -> Result<(), RepoError> {
+        self.invalidate_many_test(&[uid]).await
+    }
+
+    // Caches a copy read from MySQL after a miss that saw `seen`, see
+    // FILL_SCRIPT.
+    pub async fn fill_test(&self, uid: u64, doc: &SettingsDoc, seen: &str) -> Result<(), RepoError> {
+        let mut conn = self.conn().await?;
+
+        let script = Script::new(FILL_SCRIPT);
+        let _: i64 = fill_invocation(&script, uid, doc, seen).invoke_async(&mut conn).await?;
+
+        Ok(())
+    }
+
+    async fn write(
+        &self,
+        uid: u64,
+        expect: Expect,
+        mode: &str,
+        removed: &[&String],
+        changed: &Map<String, Value>,
+    ) -> Result<bool, RepoError> {
+        let mut conn = self.conn().await?;
+
+        let script = Script::new(WRITE_SCRIPT);
+        let version: i64 = invocation(&script, uid, expect, mode, removed, changed).invoke_async(&mut conn).await?;
+
+        Ok(version >= 0)
+    }
+
+    // One pipelined round trip for all users; users without settings come
+    // back as empty documents with version 0.
+    pub async fn get_many_test(&self, uids: &[u64]) -> Result<Vec<SettingsDoc>, RepoError> {
+        let mut conn = self.conn().await?;
+
+        let mut pipe = pipe();
+        for uid in uids {
+            let (key, version_key) = keys(*uid);
+            pipe.hgetall(key).get(version_key);
+        }
+        let values: Vec<(HashMap<String, String>, Option<u64>)> = pipe.query_async(&mut conn).await?;
+
+        Ok(values
+            .into_iter()
+            .map(|(data, version)| SettingsDoc { content: hash_to_serde_map(data), version: version.unwrap_or(0) })
+            .collect())
+    }
 
-        let mut args: Vec<(String, String)> = Vec::new();
+    // `lookup_test` for many users in one pipelined round trip.
+    pub async fn lookup_many_test(&self, uids: &[u64]) -> Result<Vec<Cached>, RepoError> {
+        let mut conn = self.conn().await?;
 
-        for (field, val) in obj {
-            let val_str = match val {
-                Value::Number(n) => n.to_string(),
-                Value::Bool(b) => b.to_string(),
-                Value::String(s) => s.clone(),
-                _ => val.to_string(),
-            };
-            args.push((field.clone(), val_str));
+        let mut pipe = pipe();
+        for uid in uids {
+            let (key, version_key) = keys(*uid);
+            pipe.hgetall(key).get(version_key);
         }
+        let values: Vec<(HashMap<String, String>, Option<String>)> = pipe.query_async(&mut conn).await?;
+
+        Ok(values.into_iter().map(|(data, version)| cached(data, version)).collect())
+    }
+
+    // Runs `add_test` for every item in one pipelined round trip. Each item
+    // is checked against its own precondition.
+    pub async fn add_many_test(&self, items: &[(u64, &Map<String, Value>, Expect)]) -> Result<Vec<bool>, RepoError> {
+        let mut conn = self.conn().await?;
+
+        let script = Script::new(WRITE_SCRIPT);
+        let invocations: Vec<_> = items
+            .iter()
+            .map(|(uid, fields, expect)| invocation(&script, *uid, *expect, "merge", &[], fields))
+            .collect();
+        // Pipelined invocations are sent as bare EVALSHA, so load the script first.
+        let mut pipe = pipe();
+        pipe.load_script(&script).ignore();
+        for invocation in &invocations {
+            pipe.invoke_script(invocation);
+        }
+        let versions: Vec<i64> = pipe.query_async(&mut conn).await?;
+
+        Ok(versions.into_iter().map(|version| version >= 0).collect())
+    }
+
+    // `fill_test` for many users in one pipelined round trip.
+    pub async fn fill_many_test(&self, docs: &[(u64, SettingsDoc, String)]) -> Result<(), RepoError> {
+        if docs.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.conn().await?;
+
+        let script = Script::new(FILL_SCRIPT);
+        let invocations: Vec<_> =
+            docs.iter().map(|(uid, doc, seen)| fill_invocation(&script, *uid, doc, seen)).collect();
+        // Pipelined invocations are sent as bare EVALSHA, so load the script first.
+        let mut pipe = pipe();
+        pipe.load_script(&script).ignore();
+        for invocation in &invocations {
+            pipe.invoke_script(invocation).ignore();
+        }
+        let _: () = pipe.query_async(&mut conn).await?;
+
+        Ok(())
+    }
+
+    // Drops the cached copies and leaves a tombstone in their version keys,
+    // so fills for misses seen before this call are rejected.
+    pub async fn invalidate_many_test(&self, uids: &[u64]) -> Result<(), RepoError> {
+        if uids.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.conn().await?;
+
+        let tombstone = format!("~{:016x}", rand::random::<u64>());
+        let mut pipe = pipe();
+        pipe.atomic();
+        for uid in uids {
+            let (key, version_key) = keys(*uid);
+            pipe.del(key).ignore().set_ex(version_key, &tombstone, FILL_TTL).ignore();
+        }
+        let _: () = pipe.query_async(&mut conn).await?;
+
+        Ok(())
+    }
+}
+
+fn invocation<'a>(
+    script: &'a Script,
+    uid: u64,
+    expect: Expect,
+    mode: &str,
+    removed: &[&String],
+    changed: &Map<String, Value>,
+) -> ScriptInvocation<'a> {
+    let (key, version_key) = keys(uid);
+    let expect = match expect {
+        Expect::Any => String::new(),
+        Expect::Exists => "*".to_string(),
+        Expect::Version(version) => version.to_string(),
+    };
+
+    let mut invocation = script.key(key);
+    invocation.key(version_key).arg(expect).arg(mode).arg(removed.len()).arg(removed);
+    for (field, value) in serde_map_to_hash(changed) {
+        invocation.arg(field).arg(value);
+    }
+    invocation
+}
+
+fn fill_invocation<'a>(script: &'a Script, uid: u64, doc: &SettingsDoc, seen: &str) -> ScriptInvocation<'a> {
+    let (key, version_key) = keys(uid);
+
+    let mut invocation = script.key(key);
+    invocation.key(version_key).arg(doc.version).arg(FILL_TTL).arg(seen);
+    for (field, value) in serde_map_to_hash(&doc.content) {
+        invocation.arg(field).arg(value);
+    }
+    invocation
+}
+
+fn cached(data: HashMap<String, String>, version: Option<String>) -> Cached {
+    match version.as_deref().map(str::parse::<u64>) {
+        Some(Ok(version)) if version > 0 => Cached::Hit(SettingsDoc { content: hash_to_serde_map(data), version }),
+        _ => Cached::Miss(version.unwrap_or_default()),
+    }
+}
+
+// The settings hash of a user and the key holding its version.
+fn keys(uid: u64) -> (String, String) {
+    let key = format!("u:{}:setting", uid);
+    (key.clone(), format!("{}:version", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{model::domain::BackendStatus, repository::fake_redis::FakeRedis};
+
+    // A Redis that has never seen the script, as after a restart or SCRIPT FLUSH.
+    #[tokio::test]
+    async fn add_many_loads_script_before_pipelined_invocations() {
+        let (redis, cache) = FakeRedis::start(BackendStatus::new(true)).await;
+        let fields = Map::from_iter([("theme".to_string(), Value::from("dark"))]);
+
+        let applied = cache.add_many_test(&[(1, &fields, Expect::Any), (2, &fields, Expect::Any)]).await.unwrap();
+
+        assert_eq!(applied, vec![true, true]);
+        let commands = redis.commands();
+        let load = commands.iter().position(|c| c.starts_with("SCRIPT LOAD")).expect("script not loaded");
+        let first_eval = commands.iter().position(|c| c.starts_with("EVALSHA")).unwrap();
+        assert!(load < first_eval);
+    }
+
+    fn doc(version: u64) -> SettingsDoc {
+        SettingsDoc { content: Map::from_iter([("v".to_string(), Value::from(version))]), version }
+    }
+
+    async fn lookup(cache: &Cache, uid: u64) -> Cached {
+        cache.lookup_test(uid).await.unwrap()
+    }
+
+    // A reader misses and loads version 1, a writer commits version 2 and
+    // invalidates, then the reader's fill arrives.
+    #[tokio::test]
+    async fn fill_loses_to_invalidation_since_miss() {
+        let (_redis, cache) = FakeRedis::start(BackendStatus::new(true)).await;
+        let Cached::Miss(seen) = lookup(&cache, 1).await else { panic!("expected a miss") };
+
+        cache.invalidate_test(1).await.unwrap();
+        cache.fill_test(1, &doc(1), &seen).await.unwrap();
+
+        let Cached::Miss(tombstone) = lookup(&cache, 1).await else { panic!("stale copy cached") };
+        assert_ne!(tombstone, seen);
+
+        // The next reader sees the tombstone and fills version 2; the late
+        // fill of version 1 does not replace it.
+        cache.fill_test(1, &doc(2), &tombstone).await.unwrap();
+        cache.fill_test(1, &doc(1), &tombstone).await.unwrap();
+        assert!(matches!(lookup(&cache, 1).await, Cached::Hit(doc) if doc.version == 2 && doc.content["v"] == 2));
+    }
+
+    #[tokio::test]
+    async fn batch_fill_skips_uids_invalidated_since_miss() {
+        let (_redis, cache) = FakeRedis::start(BackendStatus::new(true)).await;
+        let seen: Vec<String> = cache
+            .lookup_many_test(&[1, 2])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|cached| match cached {
+                Cached::Miss(seen) => seen,
+                Cached::Hit(_) => panic!("expected a miss"),
+            })
+            .collect();
 
-        let _: () = conn.hset_multiple(&key, &args).await.map_err(|e| format!("Redis hset error: {}", e))?;
+        cache.invalidate_many_test(&[2]).await.unwrap();
+        cache.fill_many_test(&[(1, doc(1), seen[0].clone()), (2, doc(1), seen[1].clone())]).await.unwrap();
 
-        Ok(Value::Null)
+        let cached = cache.lookup_many_test(&[1, 2]).await.unwrap();
+        assert!(matches!(&cached[0], Cached::Hit(doc) if doc.version == 1));
+        assert!(matches!(&cached[1], Cached::Miss(_)));
     }
 }
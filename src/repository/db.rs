@@ -1,58 +1,281 @@
 // src/repository/db.rs
-use crate::model::domain::DbClient;
+use crate::{
+    model::{
+        domain::{DbClient, DbManager},
+        entity::Settings,
+    },
+    repository::{
+        Expect, LookupError, RepoError, SETTINGS_CLUSTER, json_path,
+        tx::{UnitOfWork, Work},
+    },
+    utils::common::merge_patch,
+};
+use arc_swap::ArcSwap;
 use chrono::Utc;
-use serde_json::Map;
-use serde_json::Value;
-use sqlx::{Error, Row};
+use serde_json::{Map, Value};
+use sqlx::{Error, MySql, MySqlConnection, Pool as MysqlPool, types::Json};
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub struct Database {
-    pub db: DbClient,
+    db: Arc<ArcSwap<DbClient>>,
 }
 
 impl Database {
     pub fn new(db: DbClient) -> Self {
-        Self { db }
+        Self { db: Arc::new(ArcSwap::from_pointee(db)) }
     }
 
-    pub async fn get_test(&self, uid: u64) -> Result<Value, Error> {
-        let mut pool = self.db.relation.slave.acquire().await?;
-        let row =
-            sqlx::query("SELECT content FROM settings WHERE uid = ? LIMIT 1").bind(uid).fetch_one(&mut *pool).await?;
+    pub fn client(&self) -> Arc<DbClient> {
+        self.db.load_full()
+    }
+
+    pub fn swap(&self, db: DbClient) -> Arc<DbClient> {
+        self.db.swap(Arc::new(db))
+    }
+
+    pub fn cluster(&self, name: &str) -> Result<DbManager, LookupError> {
+        let manager =
+            self.client().clusters.get(name).cloned().ok_or_else(|| LookupError::Cluster(name.to_string()))?;
+        if !manager.status.is_up() {
+            return Err(LookupError::Unavailable(format!("mysql.{}", name)));
+        }
+        Ok(manager)
+    }
+
+    pub async fn get_test(&self, uid: u64) -> Result<Settings, RepoError> {
+        let manager = self.cluster(SETTINGS_CLUSTER)?;
+        let mut pool = manager.reader().acquire().await?;
+        Ok(get(&mut pool, uid).await?)
+    }
 
-        Ok(row.get("content"))
+    // Rows of the given users in one `IN (...)` query; users without settings
+    // are simply missing from the result.
+    pub async fn get_many_test(&self, uids: &[u64]) -> Result<Vec<Settings>, RepoError> {
+        let manager = self.cluster(SETTINGS_CLUSTER)?;
+        Ok(fetch_many(manager.reader(), uids).await?)
     }
 
-    pub async fn add_test(&self, uid: u64, fields: Value) -> Result<u64, Error> {
-        let mut pool = self.db.relation.master.acquire().await?;
+    // Reads from the master, for callers that must see their own writes.
+    pub async fn get_many_test_master(&self, uids: &[u64]) -> Result<Vec<Settings>, RepoError> {
+        Ok(fetch_many(&self.cluster(SETTINGS_CLUSTER)?.master, uids).await?)
+    }
 
-        let mut change = vec![];
-        let mut temporary: Map<String, Value> = Map::new();
-        let now = Utc::now().timestamp() as u64;
+    // The write methods return Ok(false) when `expect` does not hold.
+    pub async fn add_test(&self, uid: u64, fields: &Map<String, Value>, expect: Expect) -> Result<bool, RepoError> {
+        let mut pool = self.cluster(SETTINGS_CLUSTER)?.master.acquire().await?;
+        Ok(add(&mut pool, uid, fields, expect).await?)
+    }
 
-        if let Some(obj) = fields.as_object() {
-            for (key, val) in obj {
-                temporary.insert(key.to_string(), Value::String(val.to_string()));
+    pub async fn patch_test(&self, uid: u64, patch: &Map<String, Value>, expect: Expect) -> Result<bool, RepoError> {
+        let mut pool = self.cluster(SETTINGS_CLUSTER)?.master.acquire().await?;
+        Ok(self::patch(&mut pool, uid, patch, expect).await?)
+    }
 
-                change.push(format!("'$.{}'", key));
-                change.push(val.to_string());
+    pub async fn replace_test(
+        &self,
+        uid: u64,
+        content: &Map<String, Value>,
+        expect: Expect,
+    ) -> Result<bool, RepoError> {
+        let mut pool = self.cluster(SETTINGS_CLUSTER)?.master.acquire().await?;
+        Ok(replace(&mut pool, uid, content, expect).await?)
+    }
+
+    pub async fn delete_test(&self, uid: u64, expect: Expect) -> Result<bool, RepoError> {
+        let mut pool = self.cluster(SETTINGS_CLUSTER)?.master.acquire().await?;
+        Ok(delete(&mut pool, uid, expect).await?)
+    }
+
+    // Opens a unit of work on the master of `cluster`. It rolls back unless
+    // committed, so returning early with `?` leaves nothing behind.
+    pub async fn begin(&self, cluster: &str) -> Result<UnitOfWork<'static>, RepoError> {
+        let manager = self.cluster(cluster)?;
+        UnitOfWork::begin(cluster, &manager.master).await
+    }
+
+    // Runs `work` in a transaction on the master of `cluster`, committing
+    // when it returns Ok and rolling back when it returns Err.
+    pub async fn transaction<T, E, F>(&self, cluster: &str, work: F) -> Result<T, E>
+    where
+        F: for<'t> FnOnce(&'t mut UnitOfWork<'static>) -> Work<'t, T, E>,
+        E: From<RepoError>,
+    {
+        let mut uow = self.begin(cluster).await?;
+        match work(&mut uow).await {
+            Ok(value) => {
+                uow.commit().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                if let Err(rollback) = uow.rollback().await {
+                    tracing::warn!(cluster, "Transaction rollback failed: {}", rollback);
+                }
+                Err(e)
             }
         }
-        // println!("{:?},{:?}", change, temporary);
+    }
+}
+
+// The statements behind the settings methods, run on whatever connection the
+// caller holds: a pooled one or the one of a unit of work.
+
+pub(super) async fn get(conn: &mut MySqlConnection, uid: u64) -> Result<Settings, Error> {
+    sqlx::query_as("SELECT uid, content, version, event_time FROM settings WHERE uid = ? LIMIT 1")
+        .bind(uid)
+        .fetch_one(conn)
+        .await
+}
+
+pub(super) async fn add(
+    conn: &mut MySqlConnection,
+    uid: u64,
+    fields: &Map<String, Value>,
+    expect: Expect,
+) -> Result<bool, Error> {
+    let mut args = vec![];
+    for (key, val) in fields {
+        let path = json_path::member(key).map_err(|e| Error::InvalidArgument(e.to_string()))?;
+        args.push(path);
+        args.push(val.to_string());
+    }
+
+    if args.is_empty() {
+        return Ok(true);
+    }
+
+    // Only placeholders go into the statement; paths and values are all bound.
+    let update = format!("JSON_SET(content, {})", vec!["?, CAST(? AS JSON)"; fields.len()].join(", "));
+    tracing::debug!(uid, fields = fields.len(), "Upserting settings");
+
+    write(conn, uid, expect, fields, &update, args).await
+}
+
+// Applies a JSON Merge Patch, creating the row when the user has none yet.
+pub(super) async fn patch(
+    conn: &mut MySqlConnection,
+    uid: u64,
+    patch: &Map<String, Value>,
+    expect: Expect,
+) -> Result<bool, Error> {
+    for key in patch.keys() {
+        json_path::member(key).map_err(|e| Error::InvalidArgument(e.to_string()))?;
+    }
+
+    let mut content = Map::new();
+    merge_patch(&mut content, patch);
+    tracing::debug!(uid, fields = patch.len(), "Patching settings");
+
+    let args = vec![Value::Object(patch.clone()).to_string()];
+    write(conn, uid, expect, &content, "JSON_MERGE_PATCH(content, CAST(? AS JSON))", args).await
+}
+
+pub(super) async fn replace(
+    conn: &mut MySqlConnection,
+    uid: u64,
+    content: &Map<String, Value>,
+    expect: Expect,
+) -> Result<bool, Error> {
+    for key in content.keys() {
+        json_path::member(key).map_err(|e| Error::InvalidArgument(e.to_string()))?;
+    }
+    tracing::debug!(uid, fields = content.len(), "Replacing settings");
+
+    let args = vec![Value::Object(content.clone()).to_string()];
+    write(conn, uid, expect, content, "CAST(? AS JSON)", args).await
+}
+
+pub(super) async fn delete(conn: &mut MySqlConnection, uid: u64, expect: Expect) -> Result<bool, Error> {
+    let result = match expect {
+        Expect::Any => {
+            sqlx::query("DELETE FROM settings WHERE uid = ?").bind(uid).execute(conn).await?;
+            return Ok(true);
+        }
+        Expect::Exists => sqlx::query("DELETE FROM settings WHERE uid = ?").bind(uid).execute(conn).await?,
+        Expect::Version(version) => {
+            sqlx::query("DELETE FROM settings WHERE uid = ? AND version = ?")
+                .bind(uid)
+                .bind(version)
+                .execute(conn)
+                .await?
+        }
+    };
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Creates or updates one document. `update` is the SQL expression for the
+// new content of an existing row with `args` bound to its placeholders;
+// `initial` is the content of a new row. Every write bumps `version`, and
+// under a precondition the write is a conditional INSERT / UPDATE.
+async fn write(
+    conn: &mut MySqlConnection,
+    uid: u64,
+    expect: Expect,
+    initial: &Map<String, Value>,
+    update: &str,
+    args: Vec<String>,
+) -> Result<bool, Error> {
+    let now = Utc::now().timestamp() as u64;
 
-        if change.len() == 0 {
-            return Ok(0);
+    let result = match expect {
+        Expect::Any => {
+            let sql = format!(
+                "INSERT INTO settings (uid, content, version, event_time) VALUES (?, ?, 1, ?) ON DUPLICATE KEY UPDATE content = {}, version = version + 1, event_time = ?",
+                update
+            );
+            let mut query = sqlx::query(&sql).bind(uid).bind(Json(initial)).bind(now);
+            for arg in args {
+                query = query.bind(arg);
+            }
+            query.bind(now).execute(conn).await?
+        }
+        Expect::Version(0) => {
+            let inserted = sqlx::query("INSERT INTO settings (uid, content, version, event_time) VALUES (?, ?, 1, ?)")
+                .bind(uid)
+                .bind(Json(initial))
+                .bind(now)
+                .execute(conn)
+                .await;
+            match inserted {
+                Err(Error::Database(e)) if e.is_unique_violation() => return Ok(false),
+                inserted => inserted?,
+            }
         }
+        Expect::Exists | Expect::Version(_) => {
+            let guard = if matches!(expect, Expect::Version(_)) { " AND version = ?" } else { "" };
+            let sql = format!(
+                "UPDATE settings SET content = {}, version = version + 1, event_time = ? WHERE uid = ?{}",
+                update, guard
+            );
+            let mut query = sqlx::query(&sql);
+            for arg in args {
+                query = query.bind(arg);
+            }
+            query = query.bind(now).bind(uid);
+            if let Expect::Version(version) = expect {
+                query = query.bind(version);
+            }
+            query.execute(conn).await?
+        }
+    };
 
-        let content = serde_json::to_string(&temporary).unwrap_or_else(|_| "{}".to_string());
-        let sql = format!(
-            "INSERT INTO settings (uid, content, event_time) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE content = JSON_SET(content, {}), event_time = ?",
-            change.join(", ")
-        );
-        println!("{},{},{},{}", sql, uid, content, now);
+    Ok(result.rows_affected() > 0)
+}
 
-        let result = sqlx::query(&sql).bind(uid as i64).bind(content).bind(now).bind(now).execute(&mut *pool).await?;
+async fn fetch_many(pool: &MysqlPool<MySql>, uids: &[u64]) -> Result<Vec<Settings>, Error> {
+    if uids.is_empty() {
+        return Ok(Vec::new());
+    }
 
-        Ok(result.rows_affected())
+    let sql = format!(
+        "SELECT uid, content, version, event_time FROM settings WHERE uid IN ({})",
+        vec!["?"; uids.len()].join(", ")
+    );
+    let mut query = sqlx::query_as(&sql);
+    for uid in uids {
+        query = query.bind(uid);
     }
+
+    query.fetch_all(pool).await
 }
@@ -0,0 +1,293 @@
+// src/repository/fake_redis.rs
+//
+// A stand-in Redis for tests: keeps strings and hashes in memory, runs the
+// Lua scripts for real and records every command it gets. Expiry is ignored.
+use crate::{
+    model::domain::{BackendStatus, CacheClient, CacheManager},
+    repository::{SETTINGS_INSTANCE, cache::Cache},
+};
+use deadpool_redis::{Config, Runtime, redis::Script};
+use mlua::{Lua, Variadic};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+#[derive(Clone, Default)]
+pub struct FakeRedis {
+    commands: Arc<Mutex<Vec<String>>>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    keys: HashMap<String, Entry>,
+    // Loaded scripts by SHA1; until SCRIPT LOAD, EVALSHA fails with NOSCRIPT
+    // like on a freshly started Redis.
+    scripts: HashMap<String, String>,
+}
+
+enum Entry {
+    String(String),
+    Hash(BTreeMap<String, String>),
+}
+
+enum Reply {
+    Status(String),
+    Error(String),
+    Int(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+impl FakeRedis {
+    // Starts listening on a free local port and returns the server and a
+    // cache whose settings instance points at it.
+    pub async fn start(status: BackendStatus) -> (Self, Cache) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let fake = Self::default();
+
+        let server = fake.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(server.clone().serve(stream));
+            }
+        });
+
+        let pool = Config::from_url(url).create_pool(Some(Runtime::Tokio1)).unwrap();
+        let instances = HashMap::from([(SETTINGS_INSTANCE.to_string(), CacheManager { pool, status })]);
+        (fake, Cache::new(CacheClient { instances }))
+    }
+
+    // Commands received so far, arguments joined by spaces. Commands run by
+    // scripts are not included.
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    async fn serve(self, stream: TcpStream) {
+        let mut stream = BufReader::new(stream);
+        // Commands queued since MULTI, run together on EXEC.
+        let mut queued: Option<Vec<Vec<String>>> = None;
+        while let Some(args) = read_command(&mut stream).await {
+            self.commands.lock().unwrap().push(args.join(" "));
+            let reply = match (args[0].to_ascii_uppercase().as_str(), queued.as_mut()) {
+                ("MULTI", _) => {
+                    queued = Some(Vec::new());
+                    Reply::Status("OK".to_string())
+                }
+                ("EXEC", Some(_)) => {
+                    let mut state = self.state.lock().unwrap();
+                    Reply::Array(queued.take().unwrap().iter().map(|args| state.run(args)).collect())
+                }
+                (_, Some(commands)) => {
+                    commands.push(args);
+                    Reply::Status("QUEUED".to_string())
+                }
+                _ => self.state.lock().unwrap().run(&args),
+            };
+
+            let mut out = String::new();
+            reply.encode(&mut out);
+            if stream.get_mut().write_all(out.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl State {
+    fn run(&mut self, args: &[String]) -> Reply {
+        // Redis drops a hash once its last field is gone.
+        self.keys.retain(|_, entry| !matches!(entry, Entry::Hash(hash) if hash.is_empty()));
+        let arg = |i: usize| args.get(i).cloned().unwrap_or_default();
+        match args[0].to_ascii_uppercase().as_str() {
+            "PING" => match args.get(1) {
+                Some(arg) => Reply::Bulk(Some(arg.clone())),
+                None => Reply::Status("PONG".to_string()),
+            },
+            "GET" => match self.keys.get(&arg(1)) {
+                Some(Entry::String(value)) => Reply::Bulk(Some(value.clone())),
+                Some(Entry::Hash(_)) => wrong_type(),
+                None => Reply::Bulk(None),
+            },
+            "SET" => {
+                self.keys.insert(arg(1), Entry::String(arg(2)));
+                Reply::Status("OK".to_string())
+            }
+            "SETEX" => {
+                self.keys.insert(arg(1), Entry::String(arg(3)));
+                Reply::Status("OK".to_string())
+            }
+            "INCR" => {
+                let next = match self.keys.get(&arg(1)) {
+                    Some(Entry::String(value)) => match value.parse::<i64>() {
+                        Ok(value) => value + 1,
+                        Err(_) => return Reply::Error("ERR value is not an integer or out of range".to_string()),
+                    },
+                    Some(Entry::Hash(_)) => return wrong_type(),
+                    None => 1,
+                };
+                self.keys.insert(arg(1), Entry::String(next.to_string()));
+                Reply::Int(next)
+            }
+            "DEL" => Reply::Int(args[1..].iter().filter(|key| self.keys.remove(*key).is_some()).count() as i64),
+            "EXPIRE" => Reply::Int(self.keys.contains_key(&arg(1)) as i64),
+            "HSET" => match self.hash(&arg(1)) {
+                Some(hash) => {
+                    let pairs = args[2..].chunks(2);
+                    Reply::Int(
+                        pairs.filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none()).count() as i64
+                    )
+                }
+                None => wrong_type(),
+            },
+            "HDEL" => match self.hash(&arg(1)) {
+                Some(hash) => Reply::Int(args[2..].iter().filter(|field| hash.remove(*field).is_some()).count() as i64),
+                None => wrong_type(),
+            },
+            "HGET" => match self.hash(&arg(1)) {
+                Some(hash) => Reply::Bulk(hash.get(&arg(2)).cloned()),
+                None => wrong_type(),
+            },
+            "HMGET" => match self.hash(&arg(1)) {
+                Some(hash) => {
+                    Reply::Array(args[2..].iter().map(|field| Reply::Bulk(hash.get(field).cloned())).collect())
+                }
+                None => wrong_type(),
+            },
+            "HGETALL" => match self.hash(&arg(1)) {
+                Some(hash) => Reply::Array(
+                    hash.iter()
+                        .flat_map(|(k, v)| [Reply::Bulk(Some(k.clone())), Reply::Bulk(Some(v.clone()))])
+                        .collect(),
+                ),
+                None => wrong_type(),
+            },
+            "SCRIPT" => {
+                let hash = Script::new(&arg(2)).get_hash().to_string();
+                self.scripts.insert(hash.clone(), arg(2));
+                Reply::Bulk(Some(hash))
+            }
+            "EVALSHA" => match self.scripts.get(&arg(1)).cloned() {
+                Some(body) => self.eval(&body, &args[2..]),
+                None => Reply::Error("NOSCRIPT No matching script. Please use EVAL.".to_string()),
+            },
+            "EVAL" => self.eval(&arg(1), &args[2..]),
+            _ => Reply::Status("OK".to_string()),
+        }
+    }
+
+    // The hash at `key`, created empty if missing; None for a string.
+    fn hash(&mut self, key: &str) -> Option<&mut BTreeMap<String, String>> {
+        match self.keys.entry(key.to_string()).or_insert_with(|| Entry::Hash(BTreeMap::new())) {
+            Entry::Hash(hash) => Some(hash),
+            Entry::String(_) => None,
+        }
+    }
+
+    // Runs a script with `redis.call` bound to this keyspace. `args` starts
+    // with the number of keys, as for EVAL.
+    fn eval(&mut self, body: &str, args: &[String]) -> Reply {
+        let count = args.first().and_then(|n| n.parse::<usize>().ok()).unwrap_or(0).min(args.len() - 1);
+        let (keys, argv) = args[1..].split_at(count);
+
+        let lua = Lua::new();
+        let result = lua.scope(|scope| {
+            let call = scope.create_function_mut(|lua, args: Variadic<String>| match self.run(&args) {
+                Reply::Error(e) => Err(mlua::Error::RuntimeError(e)),
+                reply => reply.into_lua(lua),
+            })?;
+            let redis = lua.create_table()?;
+            redis.set("call", call)?;
+            lua.globals().set("redis", redis)?;
+            lua.globals().set("KEYS", keys.to_vec())?;
+            lua.globals().set("ARGV", argv.to_vec())?;
+            Reply::from_lua(lua.load(body).eval()?)
+        });
+        result.unwrap_or_else(|e| Reply::Error(format!("ERR {}", e)))
+    }
+}
+
+impl Reply {
+    fn encode(&self, out: &mut String) {
+        match self {
+            Reply::Status(s) => out.push_str(&format!("+{}\r\n", s)),
+            Reply::Error(e) => out.push_str(&format!("-{}\r\n", e)),
+            Reply::Int(i) => out.push_str(&format!(":{}\r\n", i)),
+            Reply::Bulk(Some(s)) => out.push_str(&format!("${}\r\n{}\r\n", s.len(), s)),
+            Reply::Bulk(None) => out.push_str("$-1\r\n"),
+            Reply::Array(items) => {
+                out.push_str(&format!("*{}\r\n", items.len()));
+                items.iter().for_each(|item| item.encode(out));
+            }
+        }
+    }
+
+    // Converted the way Redis hands replies to Lua: a nil reply is false.
+    fn into_lua(self, lua: &Lua) -> mlua::Result<mlua::Value<'_>> {
+        Ok(match self {
+            Reply::Int(i) => mlua::Value::Integer(i),
+            Reply::Bulk(Some(s)) => mlua::Value::String(lua.create_string(&s)?),
+            Reply::Bulk(None) => mlua::Value::Boolean(false),
+            Reply::Status(s) => mlua::Value::Table(lua.create_table_from([("ok", s)])?),
+            Reply::Error(e) => mlua::Value::Table(lua.create_table_from([("err", e)])?),
+            Reply::Array(items) => {
+                let table = lua.create_table()?;
+                for item in items {
+                    table.push(item.into_lua(lua)?)?;
+                }
+                mlua::Value::Table(table)
+            }
+        })
+    }
+
+    fn from_lua(value: mlua::Value) -> mlua::Result<Reply> {
+        Ok(match value {
+            mlua::Value::Integer(i) => Reply::Int(i),
+            mlua::Value::Number(n) => Reply::Int(n as i64),
+            mlua::Value::String(s) => Reply::Bulk(Some(s.to_str()?.to_string())),
+            mlua::Value::Boolean(true) => Reply::Int(1),
+            mlua::Value::Table(table) => match (table.get("ok")?, table.get("err")?) {
+                (Some(s), _) => Reply::Status(s),
+                (_, Some(e)) => Reply::Error(e),
+                (None, None) => {
+                    Reply::Array(table.sequence_values().map(|v| Reply::from_lua(v?)).collect::<mlua::Result<_>>()?)
+                }
+            },
+            _ => Reply::Bulk(None),
+        })
+    }
+}
+
+fn wrong_type() -> Reply {
+    Reply::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+}
+
+// One command sent as a RESP array of bulk strings; None once the client
+// hangs up.
+async fn read_command(stream: &mut BufReader<TcpStream>) -> Option<Vec<String>> {
+    let count: usize = read_line(stream).await?.strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len: usize = read_line(stream).await?.strip_prefix('$')?.parse().ok()?;
+        let mut arg = vec![0; len + 2];
+        stream.read_exact(&mut arg).await.ok()?;
+        arg.truncate(len);
+        args.push(String::from_utf8_lossy(&arg).into_owned());
+    }
+    Some(args)
+}
+
+async fn read_line(stream: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut line = String::new();
+    match stream.read_line(&mut line).await {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end().to_string()),
+    }
+}
@@ -0,0 +1,104 @@
+// src/repository/json_path.rs
+use std::fmt;
+
+// Longest object key accepted in a settings document.
+pub const MAX_KEY_LEN: usize = 64;
+
+#[derive(Debug)]
+pub enum PathError {
+    Empty,
+    TooLong(usize),
+    Control,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Empty => write!(f, "JSON key is empty"),
+            PathError::TooLong(len) => write!(f, "JSON key is {} bytes, the limit is {}", len, MAX_KEY_LEN),
+            PathError::Control => write!(f, "JSON key contains control characters"),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+// Builds the MySQL JSON path of a top-level object member. The key is always
+// quoted, so dots, `$` and `*` stay part of the name instead of becoming path
+// syntax; the result is meant to be bound as a parameter, never spliced into SQL.
+//
+//   member("a.b")        -> $."a.b"
+//   member("say \"hi\"") -> $."say \"hi\""
+pub fn member(key: &str) -> Result<String, PathError> {
+    if key.is_empty() {
+        return Err(PathError::Empty);
+    }
+    if key.len() > MAX_KEY_LEN {
+        return Err(PathError::TooLong(key.len()));
+    }
+    if key.chars().any(char::is_control) {
+        return Err(PathError::Control);
+    }
+
+    let mut path = String::with_capacity(key.len() + 4);
+    path.push_str("$.\"");
+    for c in key.chars() {
+        if c == '"' || c == '\\' {
+            path.push('\\');
+        }
+        path.push(c);
+    }
+    path.push('"');
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_key_is_quoted() {
+        assert_eq!(member("theme").unwrap(), r#"$."theme""#);
+    }
+
+    #[test]
+    fn path_syntax_stays_part_of_the_name() {
+        assert_eq!(member("a.b").unwrap(), r#"$."a.b""#);
+        assert_eq!(member("$").unwrap(), r#"$."$""#);
+        assert_eq!(member("$.a").unwrap(), r#"$."$.a""#);
+        assert_eq!(member("*").unwrap(), r#"$."*""#);
+        assert_eq!(member("a[0]").unwrap(), r#"$."a[0]""#);
+    }
+
+    #[test]
+    fn quotes_and_backslashes_are_escaped() {
+        assert_eq!(member(r#"say "hi""#).unwrap(), r#"$."say \"hi\"""#);
+        assert_eq!(member(r"a\b").unwrap(), r#"$."a\\b""#);
+        assert_eq!(member(r#"\""#).unwrap(), r#"$."\\\"""#);
+    }
+
+    #[test]
+    fn non_ascii_key_is_kept() {
+        assert_eq!(member("主题").unwrap(), r#"$."主题""#);
+    }
+
+    #[test]
+    fn empty_key_is_rejected() {
+        assert!(matches!(member(""), Err(PathError::Empty)));
+    }
+
+    #[test]
+    fn key_length_is_limited_in_bytes() {
+        assert!(member(&"a".repeat(MAX_KEY_LEN)).is_ok());
+        assert!(matches!(member(&"a".repeat(MAX_KEY_LEN + 1)), Err(PathError::TooLong(65))));
+        assert!(matches!(member(&"主".repeat(22)), Err(PathError::TooLong(66))));
+    }
+
+    #[test]
+    fn control_characters_are_rejected() {
+        for key in ["a\nb", "a\0", "\t", "a\u{7f}"] {
+            assert!(matches!(member(key), Err(PathError::Control)), "{:?}", key);
+        }
+    }
+}
@@ -1,7 +1,188 @@
-use crate::model::domain::{CacheClient, DbClient};
+use crate::{
+    model::domain::{CacheClient, DbClient},
+    utils::response::{AppError, Code},
+};
+use deadpool_redis::{
+    PoolError,
+    redis::{ErrorKind as RedisErrorKind, RedisError},
+};
+use sqlx::error::ErrorKind;
+use std::{fmt, sync::Arc};
 
 pub mod cache;
 pub mod db;
+#[cfg(test)]
+mod fake_redis;
+pub mod json_path;
+pub mod migrate;
+pub mod replica;
+pub mod settings;
+pub mod tx;
+
+// Backends used by the settings endpoints.
+pub const SETTINGS_CLUSTER: &str = "relation";
+pub const SETTINGS_INSTANCE: &str = "profile";
+
+// The state a write requires the stored document to be in, taken from the
+// request's If-Match. Version 0 stands for a document that does not exist.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expect {
+    Any,
+    Exists,
+    Version(u64),
+}
+
+#[derive(Clone, Debug)]
+pub enum LookupError {
+    Cluster(String),
+    Instance(String),
+    Unavailable(String),
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::Cluster(name) => write!(f, "unknown MySQL cluster: {}", name),
+            LookupError::Instance(name) => write!(f, "unknown Redis instance: {}", name),
+            LookupError::Unavailable(name) => write!(f, "backend unavailable: {}", name),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+impl From<LookupError> for AppError {
+    fn from(e: LookupError) -> Self {
+        match e {
+            LookupError::Unavailable(_) => AppError::Logic(Code::ServiceUnavailable),
+            _ => {
+                tracing::error!("Repository lookup failed: {}", e);
+                AppError::Logic(Code::InternalServerError)
+            }
+        }
+    }
+}
+
+// What the repository methods fail with, whichever backend they run on.
+#[derive(Clone, Debug)]
+pub enum RepoError {
+    NotFound,
+    // The document is not in the state the write expected.
+    Conflict,
+    Connection(String),
+    Timeout(String),
+    // A unique, foreign key or check constraint rejected the write.
+    Constraint(String),
+    // A value could not be encoded for or decoded from the backend.
+    Serialization(String),
+    // The input cannot be turned into a statement, e.g. a key that is no JSON path.
+    Invalid(String),
+    Other(String),
+}
+
+impl RepoError {
+    pub fn code(&self) -> Code {
+        match self {
+            RepoError::NotFound => Code::NotFound,
+            RepoError::Conflict => Code::PreconditionFailed,
+            RepoError::Constraint(_) => Code::Conflict,
+            RepoError::Invalid(_) => Code::UnprocessableEntity,
+            RepoError::Connection(_) | RepoError::Timeout(_) => Code::ServiceUnavailable,
+            RepoError::Serialization(_) | RepoError::Other(_) => Code::DbError,
+        }
+    }
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::NotFound => write!(f, "not found"),
+            RepoError::Conflict => write!(f, "version conflict"),
+            RepoError::Connection(e) => write!(f, "connection error: {}", e),
+            RepoError::Timeout(e) => write!(f, "timeout: {}", e),
+            RepoError::Constraint(e) => write!(f, "constraint violation: {}", e),
+            RepoError::Serialization(e) => write!(f, "serialization error: {}", e),
+            RepoError::Invalid(e) => write!(f, "invalid input: {}", e),
+            RepoError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RepoError {}
+
+impl From<RepoError> for AppError {
+    fn from(e: RepoError) -> Self {
+        let code = e.code();
+        match e {
+            RepoError::NotFound | RepoError::Conflict => AppError::Logic(code),
+            RepoError::Constraint(_) | RepoError::Invalid(_) => {
+                tracing::warn!("Repository rejected the request: {}", e);
+                AppError::Logic(code)
+            }
+            _ => {
+                tracing::error!("Repository error: {}", e);
+                AppError::Logic(code)
+            }
+        }
+    }
+}
+
+impl From<LookupError> for RepoError {
+    fn from(e: LookupError) -> Self {
+        match e {
+            LookupError::Unavailable(_) => RepoError::Connection(e.to_string()),
+            _ => RepoError::Other(e.to_string()),
+        }
+    }
+}
+
+impl From<sqlx::Error> for RepoError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => RepoError::NotFound,
+            sqlx::Error::PoolTimedOut => RepoError::Timeout(e.to_string()),
+            sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolClosed | sqlx::Error::WorkerCrashed => {
+                RepoError::Connection(e.to_string())
+            }
+            sqlx::Error::Database(ref db) => match db.kind() {
+                ErrorKind::UniqueViolation
+                | ErrorKind::ForeignKeyViolation
+                | ErrorKind::NotNullViolation
+                | ErrorKind::CheckViolation => RepoError::Constraint(db.message().to_string()),
+                _ => RepoError::Other(e.to_string()),
+            },
+            sqlx::Error::Encode(_) | sqlx::Error::Decode(_) | sqlx::Error::ColumnDecode { .. } => {
+                RepoError::Serialization(e.to_string())
+            }
+            sqlx::Error::InvalidArgument(e) => RepoError::Invalid(e),
+            _ => RepoError::Other(e.to_string()),
+        }
+    }
+}
+
+impl From<RedisError> for RepoError {
+    fn from(e: RedisError) -> Self {
+        if e.is_timeout() {
+            RepoError::Timeout(e.to_string())
+        } else if e.is_io_error() || e.is_connection_refusal() || e.is_connection_dropped() {
+            RepoError::Connection(e.to_string())
+        } else if e.kind() == RedisErrorKind::TypeError {
+            RepoError::Serialization(e.to_string())
+        } else {
+            RepoError::Other(e.to_string())
+        }
+    }
+}
+
+impl From<PoolError> for RepoError {
+    fn from(e: PoolError) -> Self {
+        match e {
+            PoolError::Timeout(_) => RepoError::Timeout(format!("Redis pool: {}", e)),
+            PoolError::Backend(e) => e.into(),
+            _ => RepoError::Connection(format!("Redis pool: {}", e)),
+        }
+    }
+}
 
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
@@ -14,4 +195,16 @@ impl Repository {
     pub fn new(cache: CacheClient, db: DbClient) -> Self {
         Self { cache: cache::Cache::new(cache.clone()), db: db::Database::new(db.clone()) }
     }
+
+    // New requests pick up the new pools right away; requests already running
+    // keep the old ones until they finish, so the caller drains what is returned.
+    pub fn swap(&self, cache: CacheClient, db: DbClient) -> (Arc<CacheClient>, Arc<DbClient>) {
+        (self.cache.swap(cache), self.db.swap(db))
+    }
+
+    // MySQL first, waiting for checked-out connections to come back, then Redis.
+    pub async fn close(&self) {
+        self.db.client().close().await;
+        self.cache.client().close();
+    }
 }
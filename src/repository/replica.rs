@@ -0,0 +1,112 @@
+// src/repository/replica.rs
+use crate::{
+    config::Balance,
+    repository::db::Database,
+    utils::prometheus::{DB_READ_SELECT_COUNT, DB_REPLICA_HEALTHY},
+};
+use sqlx::{MySql, Pool as MysqlPool};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use tokio::time::{Duration, interval, timeout};
+use tokio_util::sync::CancellationToken;
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug)]
+pub struct Replica {
+    pub host: String,
+    pub pool: MysqlPool<MySql>,
+    healthy: Arc<AtomicBool>,
+}
+
+impl Replica {
+    pub fn new(host: String, pool: MysqlPool<MySql>, healthy: bool) -> Self {
+        Self { host, pool, healthy: Arc::new(AtomicBool::new(healthy)) }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    fn in_use(&self) -> u32 {
+        self.pool.size().saturating_sub(self.pool.num_idle() as u32)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReadPool {
+    cluster: String,
+    replicas: Arc<Vec<Replica>>,
+    balance: Balance,
+    next: Arc<AtomicUsize>,
+}
+
+impl ReadPool {
+    pub fn new(cluster: String, replicas: Vec<Replica>, balance: Balance) -> Self {
+        Self { cluster, replicas: Arc::new(replicas), balance, next: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    pub fn replicas(&self) -> &[Replica] {
+        &self.replicas
+    }
+
+    // None when every replica has been ejected.
+    pub fn select(&self) -> Option<&Replica> {
+        let healthy: Vec<&Replica> = self.replicas.iter().filter(|r| r.is_healthy()).collect();
+        if healthy.is_empty() {
+            return None;
+        }
+
+        let replica = match self.balance {
+            Balance::RoundRobin => healthy[self.next.fetch_add(1, Ordering::Relaxed) % healthy.len()],
+            Balance::LeastConnections => healthy.into_iter().min_by_key(|r| r.in_use())?,
+        };
+        DB_READ_SELECT_COUNT.with_label_values(&[self.cluster.as_str(), replica.host.as_str()]).inc();
+
+        Some(replica)
+    }
+
+    pub fn record_master_fallback(&self) {
+        DB_READ_SELECT_COUNT.with_label_values(&[self.cluster.as_str(), "master"]).inc();
+    }
+}
+
+// Pings every replica of every cluster, ejecting the ones that fail and
+// re-admitting them once they answer again. Always checks the current
+// client, so it keeps working across pool swaps.
+pub fn start_health_check(db: Database, every: u64, shutdown: CancellationToken) {
+    if every == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(every));
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = ticker.tick() => {}
+            }
+
+            let client = db.client();
+            for (cluster, manager) in client.clusters.iter() {
+                for replica in manager.replicas.replicas() {
+                    let ok = matches!(
+                        timeout(CHECK_TIMEOUT, sqlx::query("SELECT 1").execute(&replica.pool)).await,
+                        Ok(Ok(_))
+                    );
+
+                    if replica.healthy.swap(ok, Ordering::Relaxed) != ok {
+                        if ok {
+                            tracing::info!("Replica {} of {} is back, re-admitting", replica.host, cluster);
+                        } else {
+                            tracing::error!("Replica {} of {} failed health check, ejecting", replica.host, cluster);
+                        }
+                    }
+                    DB_REPLICA_HEALTHY.with_label_values(&[cluster.as_str(), replica.host.as_str()]).set(ok as i64);
+                }
+            }
+        }
+    });
+}
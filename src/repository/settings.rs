@@ -0,0 +1,594 @@
+// src/repository/settings.rs
+use crate::{
+    config::{CacheWrite, StoreKind},
+    model::entity::{Settings, SettingsDoc},
+    repository::{
+        Expect, LookupError, RepoError, Repository, SETTINGS_CLUSTER, SETTINGS_INSTANCE,
+        cache::{Cache, Cached},
+        db::Database,
+    },
+    utils::prometheus::SETTINGS_CACHE_DIVERGENCE,
+};
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+use std::{collections::HashMap, sync::Arc};
+use tokio::{
+    sync::mpsc,
+    time::{Duration, sleep},
+};
+use tokio_util::sync::CancellationToken;
+
+// First delay of the repair task when Redis keeps failing.
+const SYNC_BACKOFF: Duration = Duration::from_millis(50);
+const REPAIR_QUEUE: usize = 1024;
+const REPAIR_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// Where user settings live. Handlers only see this trait; the backend is
+// picked by `settings_store` in the config. Every write takes the If-Match
+// precondition and fails with `RepoError::Conflict` when it does not hold.
+#[async_trait]
+pub trait SettingsStore: Send + Sync {
+    // Fails fast when a backend the store needs is unknown or down.
+    fn check(&self) -> Result<(), LookupError>;
+
+    // An empty document with version 0 when the user has no settings.
+    async fn get(&self, uid: u64) -> Result<SettingsDoc, RepoError>;
+
+    // None when the user or the field does not exist.
+    async fn get_field(&self, uid: u64, key: &str) -> Result<Option<Value>, RepoError> {
+        Ok(self.get(uid).await?.content.remove(key))
+    }
+
+    // Sets the given fields, leaving the others untouched.
+    async fn set(&self, uid: u64, fields: Map<String, Value>, expect: Expect) -> Result<(), RepoError>;
+
+    // JSON Merge Patch (RFC 7396): null removes a field, objects merge.
+    async fn patch(&self, uid: u64, patch: Map<String, Value>, expect: Expect) -> Result<(), RepoError>;
+
+    // Replaces the whole document.
+    async fn replace(&self, uid: u64, content: Map<String, Value>, expect: Expect) -> Result<(), RepoError>;
+
+    async fn delete(&self, uid: u64, expect: Expect) -> Result<(), RepoError>;
+
+    // Removing a field of a document that does not exist does nothing; it
+    // never creates the document.
+    async fn delete_field(&self, uid: u64, key: &str, expect: Expect) -> Result<(), RepoError> {
+        let patch = Map::from_iter([(key.to_string(), Value::Null)]);
+        match expect {
+            Expect::Any => match self.patch(uid, patch, Expect::Exists).await {
+                Err(RepoError::Conflict) => Ok(()),
+                result => result,
+            },
+            // Holds only while there is no document, so there is no field to remove.
+            Expect::Version(0) => applied(self.get(uid).await?.version == 0),
+            expect => self.patch(uid, patch, expect).await,
+        }
+    }
+
+    // One result per uid, in order, so a failing backend only fails the uids
+    // it was responsible for.
+    async fn get_many(&self, uids: &[u64]) -> Vec<Result<SettingsDoc, RepoError>>;
+
+    // `set` for many users, one result per item in order.
+    async fn set_many(&self, items: Vec<(u64, Map<String, Value>, Expect)>) -> Vec<Result<(), RepoError>> {
+        let mut results = Vec::with_capacity(items.len());
+        for (uid, fields, expect) in items {
+            results.push(self.set(uid, fields, expect).await);
+        }
+        results
+    }
+}
+
+pub fn new(
+    kind: StoreKind,
+    write: CacheWrite,
+    repository: &Repository,
+    shutdown: CancellationToken,
+) -> Arc<dyn SettingsStore> {
+    let db = repository.db.clone();
+    let cache = repository.cache.clone();
+
+    match kind {
+        StoreKind::Mysql => Arc::new(MysqlStore { db }),
+        StoreKind::Redis => Arc::new(RedisStore { cache }),
+        StoreKind::CacheAside => {
+            let (repair, queue) = mpsc::channel(REPAIR_QUEUE);
+            start_cache_repair(cache.clone(), queue, shutdown);
+            Arc::new(CacheAsideStore { db, cache, write, repair })
+        }
+    }
+}
+
+fn applied(ok: bool) -> Result<(), RepoError> {
+    if ok { Ok(()) } else { Err(RepoError::Conflict) }
+}
+
+// Lines rows fetched with `IN (...)` up with the requested uids; users
+// without a row get an empty document.
+fn by_uid(uids: &[u64], docs: Vec<(u64, SettingsDoc)>) -> Vec<Result<SettingsDoc, RepoError>> {
+    let mut docs: HashMap<u64, SettingsDoc> = docs.into_iter().collect();
+    uids.iter().map(|uid| Ok(docs.remove(uid).unwrap_or_default())).collect()
+}
+
+fn docs(rows: Vec<Settings>) -> Vec<(u64, SettingsDoc)> {
+    rows.into_iter().map(|row| (row.uid, row.into())).collect()
+}
+
+// Applies a batch of `set`s in one MySQL transaction, each item in its own
+// savepoint so a failing item is rolled back alone. A failure of the
+// transaction itself fails every item.
+async fn set_all(db: &Database, items: Vec<(u64, Map<String, Value>, Expect)>) -> Vec<Result<bool, RepoError>> {
+    let count = items.len();
+    let applied = db
+        .transaction(SETTINGS_CLUSTER, move |uow| {
+            Box::pin(async move {
+                let mut results = Vec::with_capacity(items.len());
+                for (uid, fields, expect) in &items {
+                    let mut savepoint = uow.savepoint().await?;
+                    match savepoint.add_test(*uid, fields, *expect).await {
+                        Ok(ok) => {
+                            savepoint.commit().await?;
+                            results.push(Ok(ok));
+                        }
+                        Err(e) => {
+                            savepoint.rollback().await?;
+                            results.push(Err(e));
+                        }
+                    }
+                }
+                Ok::<_, RepoError>(results)
+            })
+        })
+        .await;
+
+    match applied {
+        Ok(results) => results,
+        Err(e) => (0..count).map(|_| Err(e.clone())).collect(),
+    }
+}
+
+pub struct MysqlStore {
+    db: Database,
+}
+
+#[async_trait]
+impl SettingsStore for MysqlStore {
+    fn check(&self) -> Result<(), LookupError> {
+        self.db.cluster(SETTINGS_CLUSTER).map(|_| ())
+    }
+
+    async fn get(&self, uid: u64) -> Result<SettingsDoc, RepoError> {
+        match self.db.get_test(uid).await {
+            Ok(row) => Ok(row.into()),
+            Err(RepoError::NotFound) => Ok(SettingsDoc::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn set(&self, uid: u64, fields: Map<String, Value>, expect: Expect) -> Result<(), RepoError> {
+        applied(self.db.add_test(uid, &fields, expect).await?)
+    }
+
+    async fn patch(&self, uid: u64, patch: Map<String, Value>, expect: Expect) -> Result<(), RepoError> {
+        applied(self.db.patch_test(uid, &patch, expect).await?)
+    }
+
+    async fn replace(&self, uid: u64, content: Map<String, Value>, expect: Expect) -> Result<(), RepoError> {
+        applied(self.db.replace_test(uid, &content, expect).await?)
+    }
+
+    async fn delete(&self, uid: u64, expect: Expect) -> Result<(), RepoError> {
+        applied(self.db.delete_test(uid, expect).await?)
+    }
+
+    async fn get_many(&self, uids: &[u64]) -> Vec<Result<SettingsDoc, RepoError>> {
+        match self.db.get_many_test(uids).await {
+            Ok(rows) => by_uid(uids, docs(rows)),
+            Err(e) => uids.iter().map(|_| Err(e.clone())).collect(),
+        }
+    }
+
+    async fn set_many(&self, items: Vec<(u64, Map<String, Value>, Expect)>) -> Vec<Result<(), RepoError>> {
+        set_all(&self.db, items).await.into_iter().map(|r| applied(r?)).collect()
+    }
+}
+
+pub struct RedisStore {
+    cache: Cache,
+}
+
+#[async_trait]
+impl SettingsStore for RedisStore {
+    fn check(&self) -> Result<(), LookupError> {
+        self.cache.instance(SETTINGS_INSTANCE).map(|_| ())
+    }
+
+    async fn get(&self, uid: u64) -> Result<SettingsDoc, RepoError> {
+        self.cache.get_test(uid).await
+    }
+
+    async fn get_field(&self, uid: u64, key: &str) -> Result<Option<Value>, RepoError> {
+        self.cache.get_field_test(uid, key).await
+    }
+
+    async fn set(&self, uid: u64, fields: Map<String, Value>, expect: Expect) -> Result<(), RepoError> {
+        applied(self.cache.add_test(uid, &fields, expect).await?)
+    }
+
+    async fn patch(&self, uid: u64, patch: Map<String, Value>, expect: Expect) -> Result<(), RepoError> {
+        applied(self.cache.patch_test(uid, &patch, expect).await?)
+    }
+
+    async fn replace(&self, uid: u64, content: Map<String, Value>, expect: Expect) -> Result<(), RepoError> {
+        applied(self.cache.replace_test(uid, &content, expect).await?)
+    }
+
+    async fn delete(&self, uid: u64, expect: Expect) -> Result<(), RepoError> {
+        applied(self.cache.delete_test(uid, expect).await?)
+    }
+
+    async fn get_many(&self, uids: &[u64]) -> Vec<Result<SettingsDoc, RepoError>> {
+        match self.cache.get_many_test(uids).await {
+            Ok(docs) => docs.into_iter().map(Ok).collect(),
+            Err(e) => uids.iter().map(|_| Err(e.clone())).collect(),
+        }
+    }
+
+    async fn set_many(&self, items: Vec<(u64, Map<String, Value>, Expect)>) -> Vec<Result<(), RepoError>> {
+        let batch: Vec<_> = items.iter().map(|(uid, fields, expect)| (*uid, fields, *expect)).collect();
+        match self.cache.add_many_test(&batch).await {
+            Ok(applied_all) => applied_all.into_iter().map(applied).collect(),
+            Err(e) => items.iter().map(|_| Err(e.clone())).collect(),
+        }
+    }
+}
+
+// Reads Redis first and falls back to MySQL, populating Redis on a miss.
+// MySQL is the source of truth, versions included; writes commit there first
+// and then update or drop the cached copy, see `CacheWrite`.
+pub struct CacheAsideStore {
+    db: Database,
+    cache: Cache,
+    write: CacheWrite,
+    repair: mpsc::Sender<u64>,
+}
+
+impl CacheAsideStore {
+    async fn write_cache(&self, uids: &[u64]) -> Result<(), RepoError> {
+        if self.write == CacheWrite::Invalidate {
+            return self.cache.invalidate_many_test(uids).await;
+        }
+
+        // Read back from the master so the cache gets exactly what was committed.
+        let rows = match self.db.get_many_test_master(uids).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                tracing::warn!("Settings read-back failed for {} uids, invalidating instead: {}", uids.len(), e);
+                return self.cache.invalidate_many_test(uids).await;
+            }
+        };
+        // Written back as if seen absent: a tombstone from a concurrent
+        // invalidation wins, the next read refills.
+        let docs: Vec<_> = docs(rows).into_iter().map(|(uid, doc)| (uid, doc, String::new())).collect();
+        let gone: Vec<u64> = uids.iter().copied().filter(|uid| docs.iter().all(|(u, _, _)| u != uid)).collect();
+
+        self.cache.fill_many_test(&docs).await?;
+        self.cache.invalidate_many_test(&gone).await
+    }
+
+    // Tries once inline, then leaves the uids to the repair task, so a
+    // struggling Redis costs the request at most one attempt.
+    async fn sync_cache(&self, uids: &[u64]) {
+        if uids.is_empty() {
+            return;
+        }
+
+        match self.write_cache(uids).await {
+            Ok(()) => return,
+            Err(e) => tracing::warn!("Settings cache sync failed for {:?}, queueing repair: {}", uids, e),
+        }
+
+        for &uid in uids {
+            if self.repair.try_send(uid).is_ok() {
+                SETTINGS_CACHE_DIVERGENCE.with_label_values(&["queued"]).inc();
+            } else {
+                SETTINGS_CACHE_DIVERGENCE.with_label_values(&["dropped"]).inc();
+                tracing::error!("Settings cache repair queue full, uid {} may serve stale data", uid);
+            }
+        }
+    }
+
+    // Syncs the cache after a MySQL write, whether or not it was applied: a
+    // conflict means the cached copy may be behind.
+    async fn after_write(&self, uid: u64, ok: bool) -> Result<(), RepoError> {
+        self.sync_cache(&[uid]).await;
+        applied(ok)
+    }
+}
+
+#[async_trait]
+impl SettingsStore for CacheAsideStore {
+    // Redis being down only costs a cache miss, so only MySQL is required.
+    fn check(&self) -> Result<(), LookupError> {
+        self.db.cluster(SETTINGS_CLUSTER).map(|_| ())
+    }
+
+    // Cached copies always carry the MySQL version; one without predates
+    // versioning and is treated as a miss. A miss is filled only if no write
+    // invalidated the copy since, see `Cache::fill_test`.
+    async fn get(&self, uid: u64) -> Result<SettingsDoc, RepoError> {
+        let seen = match self.cache.lookup_test(uid).await {
+            Ok(Cached::Hit(doc)) => return Ok(doc),
+            Ok(Cached::Miss(seen)) => Some(seen),
+            Err(e) => {
+                tracing::warn!("Settings cache read failed for uid {}, using MySQL: {}", uid, e);
+                None
+            }
+        };
+
+        let doc: SettingsDoc = match self.db.get_test(uid).await {
+            Ok(row) => row.into(),
+            Err(RepoError::NotFound) => return Ok(SettingsDoc::default()),
+            Err(e) => return Err(e),
+        };
+
+        if let Some(seen) = seen
+            && let Err(e) = self.cache.fill_test(uid, &doc, &seen).await
+        {
+            tracing::warn!("Settings cache fill failed for uid {}: {}", uid, e);
+        }
+
+        Ok(doc)
+    }
+
+    async fn set(&self, uid: u64, fields: Map<String, Value>, expect: Expect) -> Result<(), RepoError> {
+        let ok = self.db.add_test(uid, &fields, expect).await?;
+        self.after_write(uid, ok).await
+    }
+
+    async fn patch(&self, uid: u64, patch: Map<String, Value>, expect: Expect) -> Result<(), RepoError> {
+        let ok = self.db.patch_test(uid, &patch, expect).await?;
+        self.after_write(uid, ok).await
+    }
+
+    async fn replace(&self, uid: u64, content: Map<String, Value>, expect: Expect) -> Result<(), RepoError> {
+        let ok = self.db.replace_test(uid, &content, expect).await?;
+        self.after_write(uid, ok).await
+    }
+
+    async fn delete(&self, uid: u64, expect: Expect) -> Result<(), RepoError> {
+        let ok = self.db.delete_test(uid, expect).await?;
+        self.after_write(uid, ok).await
+    }
+
+    // Hits come from one Redis pipeline, misses from one MySQL query.
+    async fn get_many(&self, uids: &[u64]) -> Vec<Result<SettingsDoc, RepoError>> {
+        // What the version key of each miss held, for the fill.
+        let mut seen: HashMap<u64, String> = HashMap::new();
+        let mut results: Vec<Option<Result<SettingsDoc, RepoError>>> = match self.cache.lookup_many_test(uids).await {
+            Ok(cached) => uids
+                .iter()
+                .zip(cached)
+                .map(|(uid, cached)| match cached {
+                    Cached::Hit(doc) => Some(Ok(doc)),
+                    Cached::Miss(version) => {
+                        seen.insert(*uid, version);
+                        None
+                    }
+                })
+                .collect(),
+            Err(e) => {
+                tracing::warn!("Settings cache batch read failed, using MySQL: {}", e);
+                uids.iter().map(|_| None).collect()
+            }
+        };
+
+        let misses: Vec<u64> = uids.iter().zip(&results).filter(|(_, r)| r.is_none()).map(|(uid, _)| *uid).collect();
+        if !misses.is_empty() {
+            let mut loaded = match self.db.get_many_test(&misses).await {
+                Ok(rows) => {
+                    let docs = docs(rows);
+                    let fills: Vec<_> =
+                        docs.iter().filter_map(|(uid, doc)| Some((*uid, doc.clone(), seen.remove(uid)?))).collect();
+                    if let Err(e) = self.cache.fill_many_test(&fills).await {
+                        tracing::warn!("Settings cache batch fill failed: {}", e);
+                    }
+                    by_uid(&misses, docs)
+                }
+                Err(e) => misses.iter().map(|_| Err(e.clone())).collect(),
+            }
+            .into_iter();
+            for result in results.iter_mut().filter(|r| r.is_none()) {
+                *result = loaded.next();
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|r| r.unwrap_or_else(|| Err(RepoError::Other("missing batch result".to_string()))))
+            .collect()
+    }
+
+    // MySQL applies the writes in one transaction; the cache is synced once
+    // for all of them afterwards.
+    async fn set_many(&self, items: Vec<(u64, Map<String, Value>, Expect)>) -> Vec<Result<(), RepoError>> {
+        let uids: Vec<u64> = items.iter().map(|(uid, _, _)| *uid).collect();
+        let results = set_all(&self.db, items).await;
+
+        let written: Vec<u64> = uids.into_iter().zip(&results).filter(|(_, r)| r.is_ok()).map(|(uid, _)| uid).collect();
+        self.sync_cache(&written).await;
+        results.into_iter().map(|r| applied(r?)).collect()
+    }
+}
+
+// Drops the Redis copy of every queued uid, backing off while Redis keeps
+// failing. Deleting is always safe: the next read refills from MySQL.
+fn start_cache_repair(cache: Cache, mut queue: mpsc::Receiver<u64>, shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        loop {
+            let uid = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                uid = queue.recv() => match uid {
+                    Some(uid) => uid,
+                    None => return,
+                },
+            };
+
+            let mut delay = SYNC_BACKOFF;
+            loop {
+                match cache.invalidate_test(uid).await {
+                    Ok(()) => {
+                        SETTINGS_CACHE_DIVERGENCE.with_label_values(&["repaired"]).inc();
+                        tracing::info!("Settings cache repaired for uid {}", uid);
+                        break;
+                    }
+                    Err(e) => {
+                        delay = (delay * 2).min(REPAIR_MAX_BACKOFF);
+                        tracing::warn!("Settings cache repair for uid {} failed, retrying in {:?}: {}", uid, delay, e);
+                    }
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = sleep(delay) => {}
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Balance,
+        model::domain::{BackendStatus, DbClient, DbManager},
+        repository::{fake_redis::FakeRedis, migrate, replica::ReadPool},
+    };
+    use sqlx::mysql::MySqlPoolOptions;
+    use tokio::time::timeout;
+
+    fn store(cache: Cache, repair: mpsc::Sender<u64>) -> CacheAsideStore {
+        let db = Database::new(DbClient { clusters: HashMap::new() });
+        CacheAsideStore { db, cache, write: CacheWrite::Invalidate, repair }
+    }
+
+    fn divergence(stage: &str) -> u64 {
+        SETTINGS_CACHE_DIVERGENCE.with_label_values(&[stage]).get()
+    }
+
+    #[tokio::test]
+    async fn sync_cache_invalidates_written_uids() {
+        let (_redis, cache) = FakeRedis::start(BackendStatus::new(true)).await;
+        let (repair, mut queue) = mpsc::channel(1);
+        cache.fill_test(3, &SettingsDoc { content: Map::new(), version: 1 }, "").await.unwrap();
+
+        store(cache.clone(), repair).sync_cache(&[3]).await;
+
+        assert!(matches!(cache.lookup_test(3).await.unwrap(), Cached::Miss(_)));
+        assert!(queue.try_recv().is_err());
+    }
+
+    // Redis going away after MySQL committed: the uid is queued for repair,
+    // or counted as dropped once the queue is full.
+    #[tokio::test]
+    async fn sync_cache_failure_queues_repair() {
+        let (redis, cache) = FakeRedis::start(BackendStatus::new(false)).await;
+        let (repair, mut queue) = mpsc::channel(1);
+        let (queued, dropped) = (divergence("queued"), divergence("dropped"));
+
+        store(cache, repair).sync_cache(&[1, 2]).await;
+
+        assert_eq!(queue.try_recv().ok(), Some(1));
+        assert!(queue.try_recv().is_err());
+        assert_eq!(divergence("queued"), queued + 1);
+        assert_eq!(divergence("dropped"), dropped + 1);
+        assert!(redis.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn repair_retries_until_redis_recovers() {
+        let status = BackendStatus::new(false);
+        let (redis, cache) = FakeRedis::start(status.clone()).await;
+        let (repair, queue) = mpsc::channel(1);
+        let shutdown = CancellationToken::new();
+        let repaired = divergence("repaired");
+
+        start_cache_repair(cache, queue, shutdown.clone());
+        repair.send(7).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(divergence("repaired"), repaired);
+
+        status.set(true);
+        timeout(Duration::from_secs(5), async {
+            while divergence("repaired") == repaired {
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("repair did not run after Redis recovered");
+
+        assert!(redis.commands().contains(&"DEL u:7:setting".to_string()));
+        shutdown.cancel();
+    }
+
+    // Documents kept in memory, with the precondition rules of the real stores.
+    fn fields(pairs: &[(&str, &str)]) -> Map<String, Value> {
+        pairs.iter().map(|(k, v)| (k.to_string(), Value::from(*v))).collect()
+    }
+
+    // Shared by the store tests below; `uid` must have no settings yet.
+    async fn check_delete_field_of_missing_document(store: &dyn SettingsStore, uid: u64) {
+        store.delete_field(uid, "theme", Expect::Any).await.unwrap();
+        store.delete_field(uid, "theme", Expect::Version(0)).await.unwrap();
+        assert!(matches!(store.delete_field(uid, "theme", Expect::Exists).await, Err(RepoError::Conflict)));
+        assert!(matches!(store.delete_field(uid, "theme", Expect::Version(3)).await, Err(RepoError::Conflict)));
+
+        let doc = store.get(uid).await.unwrap();
+        assert_eq!((doc.content, doc.version), (Map::new(), 0));
+    }
+
+    async fn check_delete_field_of_existing_document(store: &dyn SettingsStore, uid: u64) {
+        store.set(uid, fields(&[("theme", "dark"), ("lang", "en")]), Expect::Any).await.unwrap();
+
+        store.delete_field(uid, "theme", Expect::Any).await.unwrap();
+        assert!(matches!(store.delete_field(uid, "lang", Expect::Version(0)).await, Err(RepoError::Conflict)));
+        assert!(matches!(store.delete_field(uid, "lang", Expect::Version(1)).await, Err(RepoError::Conflict)));
+
+        let doc = store.get(uid).await.unwrap();
+        assert_eq!((doc.content, doc.version), (fields(&[("lang", "en")]), 2));
+
+        store.delete_field(uid, "lang", Expect::Version(2)).await.unwrap();
+        assert_eq!(store.get(uid).await.unwrap().content, Map::new());
+    }
+
+    #[tokio::test]
+    async fn redis_delete_field_of_missing_document_creates_nothing() {
+        let (_redis, cache) = FakeRedis::start(BackendStatus::new(true)).await;
+        check_delete_field_of_missing_document(&RedisStore { cache }, 1).await;
+    }
+
+    #[tokio::test]
+    async fn redis_delete_field_removes_field_of_existing_document() {
+        let (_redis, cache) = FakeRedis::start(BackendStatus::new(true)).await;
+        check_delete_field_of_existing_document(&RedisStore { cache }, 1).await;
+    }
+
+    // Needs a disposable MySQL-compatible database, see migrate.rs.
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn mysql_delete_field() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let master = MySqlPoolOptions::new().max_connections(2).connect(&url).await.unwrap();
+        migrate::up(&master).await.unwrap();
+
+        let replicas = ReadPool::new(SETTINGS_CLUSTER.to_string(), Vec::new(), Balance::default());
+        let manager = DbManager { master, replicas, status: BackendStatus::new(true) };
+        let db = Database::new(DbClient { clusters: HashMap::from([(SETTINGS_CLUSTER.to_string(), manager)]) });
+        let store = MysqlStore { db };
+
+        let uid = rand::random::<u32>() as u64 + 1;
+        check_delete_field_of_missing_document(&store, uid).await;
+        check_delete_field_of_existing_document(&store, uid).await;
+        store.delete(uid, Expect::Any).await.unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+// src/repository/tx.rs
+use crate::{
+    model::entity::Settings,
+    repository::{Expect, RepoError, db},
+    utils::prometheus::{DB_TX_DURATION, DB_TX_ROLLBACK},
+};
+use serde_json::{Map, Value};
+use sqlx::{Connection, MySql, MySqlConnection, Pool as MysqlPool, Transaction};
+use std::{future::Future, pin::Pin, time::Instant};
+
+// The future a closure passed to `Database::transaction` returns.
+pub type Work<'t, T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send + 't>>;
+
+// A MySQL transaction, or a savepoint inside one. Dropping it without
+// `commit` rolls it back: sqlx queues the ROLLBACK on the connection and it
+// runs before the connection is used again.
+pub struct UnitOfWork<'c> {
+    tx: Option<Transaction<'c, MySql>>,
+    cluster: String,
+    depth: usize,
+    started: Instant,
+}
+
+impl UnitOfWork<'static> {
+    pub(super) async fn begin(cluster: &str, pool: &MysqlPool<MySql>) -> Result<Self, RepoError> {
+        let tx = pool.begin().await?;
+        Ok(Self { tx: Some(tx), cluster: cluster.to_string(), depth: 0, started: Instant::now() })
+    }
+}
+
+impl<'c> UnitOfWork<'c> {
+    // The connection the transaction runs on, for statements the methods
+    // below do not cover.
+    pub fn conn(&mut self) -> &mut MySqlConnection {
+        self.tx.as_mut().expect("unit of work used after it finished")
+    }
+
+    // Opens a nested unit of work backed by a SAVEPOINT. Committing it
+    // releases the savepoint; rolling it back or dropping it undoes only what
+    // ran inside it.
+    pub async fn savepoint(&mut self) -> Result<UnitOfWork<'_>, RepoError> {
+        let cluster = self.cluster.clone();
+        let depth = self.depth + 1;
+        let tx = self.conn().begin().await?;
+        Ok(UnitOfWork { tx: Some(tx), cluster, depth, started: Instant::now() })
+    }
+
+    pub async fn commit(mut self) -> Result<(), RepoError> {
+        let tx = self.tx.take().expect("unit of work used after it finished");
+        match tx.commit().await {
+            Ok(()) => {
+                self.record("commit", None);
+                Ok(())
+            }
+            Err(e) => {
+                self.record("rollback", Some("commit_failed"));
+                Err(e.into())
+            }
+        }
+    }
+
+    pub async fn rollback(mut self) -> Result<(), RepoError> {
+        let tx = self.tx.take().expect("unit of work used after it finished");
+        self.record("rollback", Some("error"));
+        Ok(tx.rollback().await?)
+    }
+
+    fn record(&self, outcome: &str, reason: Option<&str>) {
+        let scope = if self.depth == 0 { "transaction" } else { "savepoint" };
+        if self.depth == 0 {
+            DB_TX_DURATION.with_label_values(&[&self.cluster, outcome]).observe(self.started.elapsed().as_secs_f64());
+        }
+        if let Some(reason) = reason {
+            DB_TX_ROLLBACK.with_label_values(&[&self.cluster, scope, reason]).inc();
+        }
+    }
+}
+
+// The settings statements of `Database`, run inside the unit of work.
+#[allow(dead_code)]
+impl UnitOfWork<'_> {
+    pub async fn get_test(&mut self, uid: u64) -> Result<Settings, RepoError> {
+        Ok(db::get(self.conn(), uid).await?)
+    }
+
+    pub async fn add_test(&mut self, uid: u64, fields: &Map<String, Value>, expect: Expect) -> Result<bool, RepoError> {
+        Ok(db::add(self.conn(), uid, fields, expect).await?)
+    }
+
+    pub async fn patch_test(
+        &mut self,
+        uid: u64,
+        patch: &Map<String, Value>,
+        expect: Expect,
+    ) -> Result<bool, RepoError> {
+        Ok(db::patch(self.conn(), uid, patch, expect).await?)
+    }
+
+    pub async fn replace_test(
+        &mut self,
+        uid: u64,
+        content: &Map<String, Value>,
+        expect: Expect,
+    ) -> Result<bool, RepoError> {
+        Ok(db::replace(self.conn(), uid, content, expect).await?)
+    }
+
+    pub async fn delete_test(&mut self, uid: u64, expect: Expect) -> Result<bool, RepoError> {
+        Ok(db::delete(self.conn(), uid, expect).await?)
+    }
+}
+
+impl Drop for UnitOfWork<'_> {
+    fn drop(&mut self) {
+        if self.tx.is_some() {
+            tracing::warn!(cluster = %self.cluster, depth = self.depth, "Unit of work dropped without commit, rolling back");
+            self.record("rollback", Some("dropped"));
+        }
+    }
+}
@@ -1,5 +1,9 @@
 // src/router.rs
-use crate::{handler::common, model::domain::AppState, utils::prometheus};
+use crate::{
+    handler::{admin, common, health, settings},
+    model::domain::AppState,
+    utils::{prometheus, trace},
+};
 use axum::{
     Router, middleware,
     routing::{get, post},
@@ -10,9 +14,20 @@ pub async fn init(state: Arc<AppState>) -> Router {
     Router::new()
         .route("/", get(common::ok))
         .route("/metrics", get(prometheus::prometheus_handler))
+        .route("/health/live", get(health::live))
+        .route("/health/ready", get(health::ready))
+        .route("/admin/circuits", get(admin::circuits))
         .route("/get/{uid}/something", get(common::get_something))
         .route("/set/{uid}/something", post(common::set_something))
+        .route("/settings/batch/get", post(settings::batch_get))
+        .route("/settings/batch/set", post(settings::batch_set))
+        .route(
+            "/settings/{uid}",
+            get(settings::get).put(settings::replace).patch(settings::patch).delete(settings::delete),
+        )
+        .route("/settings/{uid}/{key}", get(settings::get_field).delete(settings::delete_field))
         .layer(middleware::from_fn_with_state(state.clone(), prometheus::metrics_middleware))
+        .layer(middleware::from_fn(trace::trace_middleware))
         .fallback(common::not_found)
         .with_state(state)
 }
@@ -0,0 +1,298 @@
+// src/utils/breaker.rs
+use crate::{config::BreakerConf, utils::prometheus::FETCH_CIRCUIT_STATE};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl State {
+    fn gauge(&self) -> i64 {
+        match self {
+            State::Closed => 0,
+            State::Open => 1,
+            State::HalfOpen => 2,
+        }
+    }
+}
+
+// What GET /admin/circuits reports for one host.
+#[derive(Serialize, Debug)]
+pub struct CircuitStatus {
+    pub host: String,
+    pub state: State,
+    pub requests: u32,
+    pub failures: u32,
+    // Seconds until an open circuit lets probes through.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: State,
+    // Calls and failures counted since `window_start`; the count restarts
+    // once the window has passed.
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+    opened_at: Instant,
+    // Half-open calls let through and not yet recorded.
+    probes: u32,
+    // Bumped on every state change; outcomes of calls let through in an
+    // earlier state are ignored.
+    generation: u64,
+}
+
+impl Circuit {
+    fn new(now: Instant) -> Self {
+        Self {
+            state: State::Closed,
+            window_start: now,
+            requests: 0,
+            failures: 0,
+            opened_at: now,
+            probes: 0,
+            generation: 0,
+        }
+    }
+}
+
+// A call let through by `Breaker::allow`. Dropping it without `record`, as
+// when the caller's future is cancelled mid-call, frees its probe slot
+// without counting for or against the host.
+#[must_use]
+#[derive(Debug)]
+pub struct Permit {
+    breaker: Breaker,
+    host: String,
+    generation: u64,
+    recorded: bool,
+}
+
+impl Permit {
+    pub fn record(mut self, ok: bool) {
+        self.recorded = true;
+        self.breaker.record(&self.host, self.generation, ok);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.release(&self.host, self.generation);
+        }
+    }
+}
+
+// One circuit per upstream host, shared by every clone.
+#[derive(Clone, Debug)]
+pub struct Breaker {
+    conf: BreakerConf,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+
+impl Breaker {
+    pub fn new(conf: BreakerConf) -> Self {
+        Self { conf, circuits: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    // A permit when a call to `host` may go ahead, None when its circuit
+    // rejects it. The call reports its outcome through the permit.
+    pub fn allow(&self, host: &str) -> Option<Permit> {
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let circuit = circuits.entry(host.to_string()).or_insert_with(|| Circuit::new(now));
+
+        let allowed = match circuit.state {
+            State::Closed => true,
+            State::Open if now.duration_since(circuit.opened_at) < self.cooldown() => false,
+            State::Open => {
+                self.transition(host, circuit, State::HalfOpen);
+                circuit.probes = 1;
+                true
+            }
+            State::HalfOpen if circuit.probes < self.conf.half_open_probes => {
+                circuit.probes += 1;
+                true
+            }
+            State::HalfOpen => false,
+        };
+        let generation = circuit.generation;
+        allowed.then(|| Permit { breaker: self.clone(), host: host.to_string(), generation, recorded: false })
+    }
+
+    // A call let through before the last state change, such as a slow call
+    // from before the circuit opened, no longer counts.
+    fn record(&self, host: &str, generation: u64, ok: bool) {
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(circuit) = circuits.get_mut(host).filter(|c| c.generation == generation) else {
+            return;
+        };
+
+        match circuit.state {
+            // The first probe to finish decides: success closes the circuit, failure
+            // reopens it for another cooldown.
+            State::HalfOpen => {
+                circuit.probes = circuit.probes.saturating_sub(1);
+                if ok {
+                    circuit.window_start = now;
+                    circuit.requests = 0;
+                    circuit.failures = 0;
+                    self.transition(host, circuit, State::Closed);
+                } else {
+                    circuit.opened_at = now;
+                    self.transition(host, circuit, State::Open);
+                }
+            }
+            State::Closed => {
+                if now.duration_since(circuit.window_start) >= Duration::from_secs(self.conf.window) {
+                    circuit.window_start = now;
+                    circuit.requests = 0;
+                    circuit.failures = 0;
+                }
+                circuit.requests += 1;
+                circuit.failures += !ok as u32;
+
+                let rate = circuit.failures as f64 / circuit.requests as f64;
+                if circuit.requests >= self.conf.min_requests && rate >= self.conf.failure_rate {
+                    circuit.opened_at = now;
+                    self.transition(host, circuit, State::Open);
+                }
+            }
+            // No calls are let through while open.
+            State::Open => {}
+        }
+    }
+
+    // Frees the probe slot of a call that ended without an outcome.
+    fn release(&self, host: &str, generation: u64) {
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(circuit) = circuits.get_mut(host)
+            && circuit.generation == generation
+            && circuit.state == State::HalfOpen
+        {
+            circuit.probes = circuit.probes.saturating_sub(1);
+        }
+    }
+
+    pub fn circuits(&self) -> Vec<CircuitStatus> {
+        let now = Instant::now();
+        let circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut status: Vec<CircuitStatus> = circuits
+            .iter()
+            .map(|(host, c)| CircuitStatus {
+                host: host.clone(),
+                state: c.state,
+                requests: c.requests,
+                failures: c.failures,
+                retry_in: (c.state == State::Open).then(|| {
+                    self.cooldown().saturating_sub(now.duration_since(c.opened_at)).as_secs_f64().ceil() as u64
+                }),
+            })
+            .collect();
+        status.sort_by(|a, b| a.host.cmp(&b.host));
+        status
+    }
+
+    fn cooldown(&self) -> Duration {
+        Duration::from_secs(self.conf.cooldown)
+    }
+
+    fn transition(&self, host: &str, circuit: &mut Circuit, state: State) {
+        match state {
+            State::Open if circuit.state == State::HalfOpen => {
+                tracing::error!("Circuit for {} reopened, probe failed", host)
+            }
+            State::Open => tracing::error!(
+                "Circuit for {} opened after {} of {} calls failed",
+                host,
+                circuit.failures,
+                circuit.requests
+            ),
+            State::HalfOpen => tracing::info!("Circuit for {} half-open, probing", host),
+            State::Closed => tracing::info!("Circuit for {} closed", host),
+        }
+        circuit.state = state;
+        circuit.generation += 1;
+        FETCH_CIRCUIT_STATE.with_label_values(&[host]).set(state.gauge());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> Breaker {
+        Breaker::new(BreakerConf { failure_rate: 0.5, window: 10, min_requests: 2, cooldown: 0, half_open_probes: 1 })
+    }
+
+    fn state(breaker: &Breaker, host: &str) -> State {
+        breaker.circuits().into_iter().find(|c| c.host == host).unwrap().state
+    }
+
+    fn open(breaker: &Breaker, host: &str) {
+        for _ in 0..2 {
+            breaker.allow(host).unwrap().record(false);
+        }
+        assert_eq!(state(breaker, host), State::Open);
+    }
+
+    #[test]
+    fn probe_outcome_closes_or_reopens() {
+        let breaker = breaker();
+        open(&breaker, "a:80");
+
+        let probe = breaker.allow("a:80").unwrap();
+        assert_eq!(state(&breaker, "a:80"), State::HalfOpen);
+        assert!(breaker.allow("a:80").is_none());
+        probe.record(false);
+        assert_eq!(state(&breaker, "a:80"), State::Open);
+
+        breaker.allow("a:80").unwrap().record(true);
+        assert_eq!(state(&breaker, "a:80"), State::Closed);
+    }
+
+    // A probe whose caller went away must not hold the host half-open forever.
+    #[test]
+    fn dropped_probe_frees_its_slot() {
+        let breaker = breaker();
+        open(&breaker, "b:80");
+
+        drop(breaker.allow("b:80").unwrap());
+        assert_eq!(state(&breaker, "b:80"), State::HalfOpen);
+
+        breaker.allow("b:80").expect("probe slot not freed").record(true);
+        assert_eq!(state(&breaker, "b:80"), State::Closed);
+    }
+
+    // Calls let through while closed that end after the circuit went
+    // half-open neither close it nor free the probe's slot.
+    #[test]
+    fn calls_from_before_opening_are_ignored() {
+        let breaker = breaker();
+        let slow = breaker.allow("c:80").unwrap();
+        let cancelled = breaker.allow("c:80").unwrap();
+        open(&breaker, "c:80");
+        let probe = breaker.allow("c:80").unwrap();
+
+        slow.record(true);
+        drop(cancelled);
+        assert_eq!(state(&breaker, "c:80"), State::HalfOpen);
+        assert!(breaker.allow("c:80").is_none());
+
+        probe.record(true);
+        assert_eq!(state(&breaker, "c:80"), State::Closed);
+    }
+}
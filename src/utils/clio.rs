@@ -1,39 +1,75 @@
 // src/utils/clio.rs
+use std::io::Read;
 use std::path::PathBuf;
-use std::process::{Command, Output};
+use std::process::{Command, Output, Stdio};
+use std::thread::{self, JoinHandle, sleep};
+use std::time::{Duration, Instant};
+
+// How long a lookup may take before the tool is killed; config reloads wait
+// on it.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub struct ClioTool {
     command: String,              // such as "get_conf", "get_host", "get_allhost", "get_batch_keys"
     path: PathBuf,                // such as "/demo/conf"
     binary_path: Option<PathBuf>, // such as "qconf", default "clio-tool"
+    timeout: Duration,
 }
 
 impl ClioTool {
     pub fn new(command: impl Into<String>, path: impl Into<PathBuf>) -> Self {
-        Self { command: command.into(), path: path.into(), binary_path: None }
+        Self { command: command.into(), path: path.into(), binary_path: None, timeout: DEFAULT_TIMEOUT }
     }
 
-    #[allow(dead_code)]
     pub fn binary(mut self, path: impl Into<PathBuf>) -> Self {
         self.binary_path = Some(path.into());
         self
     }
 
+    #[allow(dead_code)]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     pub fn execute(&self) -> Result<Output, Box<dyn std::error::Error>> {
-        let output = Command::new(self.binary_path.as_deref().unwrap_or("clio-tool".as_ref()))
+        let mut child = Command::new(self.binary_path.as_deref().unwrap_or("clio-tool".as_ref()))
             .arg(&self.command)
             .arg(&self.path)
-            .output()
-            .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Drained while waiting, so a tool writing more than the pipe buffer
+        // does not block on a full pipe.
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("clio-tool timed out after {:?}", self.timeout),
+                )));
+            }
+            sleep(POLL_INTERVAL);
+        };
+        let output =
+            Output { status, stdout: stdout.join().unwrap_or_default(), stderr: stderr.join().unwrap_or_default() };
 
         if !output.status.success() {
             let code = output.status.code().unwrap_or(-1);
-            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = String::from_utf8_lossy(&output.stderr).trim_end().to_string();
 
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("clio-tool failed (exit {code}): {stderr}"),
-            )));
+            return Err(Box::new(std::io::Error::other(format!("clio-tool failed (exit {code}): {stderr}"))));
         }
 
         Ok(output)
@@ -50,12 +86,10 @@ impl ClioTool {
         Self::new("get_conf", path)
     }
 
-    #[allow(dead_code)]
     pub fn get_host(path: impl Into<PathBuf>) -> Self {
         Self::new("get_host", path)
     }
 
-    #[allow(dead_code)]
     pub fn get_allhost(path: impl Into<PathBuf>) -> Self {
         Self::new("get_allhost", path)
     }
@@ -70,3 +104,154 @@ impl ClioTool {
         paths.iter().map(|path| ClioTool::get_conf(*path).get_output()).collect::<Result<Vec<_>, _>>()
     }
 }
+
+// Reads a pipe of the child to the end on its own thread.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+// Config is first loaded before logging is set up; until then fallbacks go
+// to stderr instead of being dropped.
+fn warn(message: String) {
+    if tracing::dispatcher::has_been_set() {
+        tracing::warn!("{}", message);
+    } else {
+        eprintln!("WARN {}", message);
+    }
+}
+
+// Resolves endpoints through clio-tool, keeping the static value from the
+// config file as a fallback when the tool is missing or the path is unknown.
+#[derive(Clone, Debug, Default)]
+pub struct ClioProvider {
+    binary_path: Option<PathBuf>,
+}
+
+impl ClioProvider {
+    pub fn new(binary_path: Option<PathBuf>) -> Self {
+        Self { binary_path }
+    }
+
+    fn tool(&self, tool: ClioTool) -> ClioTool {
+        match &self.binary_path {
+            Some(path) => tool.binary(path),
+            None => tool,
+        }
+    }
+
+    pub fn host(&self, path: Option<&str>, fallback: Option<String>) -> Option<String> {
+        let Some(path) = path else { return fallback };
+
+        match self.tool(ClioTool::get_host(path)).get_output() {
+            Ok(host) if !host.is_empty() => Some(host),
+            Ok(_) => {
+                warn(format!("clio-tool get_host {} returned nothing, using static value", path));
+                fallback
+            }
+            Err(e) => {
+                warn(format!("clio-tool get_host {} failed ({}), using static value", path, e));
+                fallback
+            }
+        }
+    }
+
+    pub fn all_hosts(&self, path: Option<&str>, fallback: Option<Vec<String>>) -> Option<Vec<String>> {
+        let Some(path) = path else { return fallback };
+
+        match self.tool(ClioTool::get_allhost(path)).get_output() {
+            Ok(out) if !out.trim().is_empty() => Some(out.split_whitespace().map(String::from).collect()),
+            Ok(_) => {
+                warn(format!("clio-tool get_allhost {} returned nothing, using static value", path));
+                fallback
+            }
+            Err(e) => {
+                warn(format!("clio-tool get_allhost {} failed ({}), using static value", path, e));
+                fallback
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, os::unix::fs::PermissionsExt, sync::OnceLock};
+
+    const SCRIPT: &str = r#"#!/bin/sh
+case "$1 $2" in
+  "get_host /db/master") echo 10.0.0.1:3306 ;;
+  "get_allhost /db/slaves") printf '10.0.0.2:3306\n10.0.0.3:3306\n' ;;
+  "get_host /db/empty") ;;
+  "get_host /db/slow") sleep 5 ;;
+  "get_conf /big") head -c 200000 /dev/zero | tr '\0' x ;;
+  *) echo "no such path: $2" >&2; exit 1 ;;
+esac
+"#;
+
+    // Written once, before any test runs it, so no test execs the file while
+    // another still has it open for writing.
+    fn fake_tool() -> PathBuf {
+        static TOOL: OnceLock<PathBuf> = OnceLock::new();
+        TOOL.get_or_init(|| {
+            let path = std::env::temp_dir().join(format!("fake-clio-tool-{}", std::process::id()));
+            fs::write(&path, SCRIPT).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        })
+        .clone()
+    }
+
+    fn provider() -> ClioProvider {
+        ClioProvider::new(Some(fake_tool()))
+    }
+
+    #[test]
+    fn host_comes_from_the_tool() {
+        assert_eq!(provider().host(Some("/db/master"), Some("static".into())), Some("10.0.0.1:3306".into()));
+    }
+
+    #[test]
+    fn all_hosts_splits_the_output() {
+        let hosts = provider().all_hosts(Some("/db/slaves"), None).unwrap();
+        assert_eq!(hosts, vec!["10.0.0.2:3306", "10.0.0.3:3306"]);
+    }
+
+    #[test]
+    fn static_value_is_used_when_the_tool_has_nothing() {
+        let fallback = Some("static".to_string());
+
+        assert_eq!(provider().host(Some("/db/empty"), fallback.clone()), fallback);
+        assert_eq!(provider().host(Some("/db/unknown"), fallback.clone()), fallback);
+        assert_eq!(provider().all_hosts(Some("/db/unknown"), Some(vec![])), Some(vec![]));
+        assert_eq!(provider().host(None, fallback.clone()), fallback);
+    }
+
+    #[test]
+    fn static_value_is_used_when_the_tool_is_missing() {
+        let provider = ClioProvider::new(Some("/nonexistent/clio-tool".into()));
+        assert_eq!(provider.host(Some("/db/master"), Some("static".into())), Some("static".into()));
+    }
+
+    // More than a pipe buffer holds.
+    #[test]
+    fn large_output_is_read_while_waiting() {
+        let out = ClioTool::get_conf("/big").binary(fake_tool()).timeout(Duration::from_secs(2)).get_output().unwrap();
+        assert_eq!(out.len(), 200000);
+    }
+
+    #[test]
+    fn hung_tool_is_killed() {
+        let start = Instant::now();
+        let result =
+            ClioTool::get_host("/db/slow").binary(fake_tool()).timeout(Duration::from_millis(200)).get_output();
+
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -1,9 +1,37 @@
 use serde_json::{Map, Value};
 use std::collections::HashMap;
 
-pub fn hashmap_to_serde_map<T>(input: HashMap<String, T>) -> Map<String, Value>
-where
-    T: Into<Value>,
-{
-    input.into_iter().map(|(k, v)| (k, v.into())).collect()
+// Redis hash fields hold JSON-encoded values so that numbers, booleans,
+// arrays and objects come back with their type. Fields written before that
+// are not valid JSON and are read back as plain strings.
+pub fn hash_to_serde_map(input: HashMap<String, String>) -> Map<String, Value> {
+    input.into_iter().map(|(k, v)| (k, serde_json::from_str(&v).unwrap_or(Value::String(v)))).collect()
+}
+
+pub fn serde_map_to_hash(input: &Map<String, Value>) -> Vec<(String, String)> {
+    input.iter().map(|(k, v)| (k.clone(), v.to_string())).collect()
+}
+
+// JSON Merge Patch (RFC 7396) on an object: null removes a key, objects merge
+// recursively and any other value replaces what was there.
+pub fn merge_patch(target: &mut Map<String, Value>, patch: &Map<String, Value>) {
+    for (key, value) in patch {
+        match value {
+            Value::Null => {
+                target.remove(key);
+            }
+            Value::Object(inner) => {
+                let slot = target.entry(key.clone()).or_insert_with(|| Value::Object(Map::new()));
+                if !slot.is_object() {
+                    *slot = Value::Object(Map::new());
+                }
+                if let Value::Object(slot) = slot {
+                    merge_patch(slot, inner);
+                }
+            }
+            _ => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
 }
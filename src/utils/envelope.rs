@@ -0,0 +1,81 @@
+// src/utils/envelope.rs
+use serde_json::Value;
+use std::fmt;
+
+// Why an envelope did not yield a payload.
+#[derive(Debug)]
+pub enum EnvelopeError {
+    // The body is not in the shape the envelope expects.
+    Malformed(String),
+    // The upstream reported a failure in the body.
+    Business(i64, String),
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::Malformed(e) => write!(f, "malformed envelope: {}", e),
+            EnvelopeError::Business(code, msg) => write!(f, "business error: code={}, msg={}", code, msg),
+        }
+    }
+}
+
+// How an upstream wraps its payload. `open` takes the decoded JSON body and
+// returns the payload, None when the body carries none.
+pub trait Envelope: fmt::Debug + Send + Sync {
+    fn open(&self, body: Value) -> Result<Option<Value>, EnvelopeError>;
+}
+
+// APIs that return the payload as the whole body.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Raw;
+
+impl Envelope for Raw {
+    fn open(&self, body: Value) -> Result<Option<Value>, EnvelopeError> {
+        Ok(Some(body).filter(|v| !v.is_null()))
+    }
+}
+
+// `{ <code>, <message>, <data> }` bodies whose code is one of `success` on
+// success. The default is our own `{ code, message, data }` with 200; other
+// APIs use e.g. `Fields::new("errcode", "errmsg", "data", &[0])`.
+#[derive(Clone, Debug)]
+pub struct Fields {
+    code: String,
+    message: String,
+    data: String,
+    success: Vec<i64>,
+}
+
+#[allow(dead_code)]
+impl Fields {
+    pub fn new(code: &str, message: &str, data: &str, success: &[i64]) -> Self {
+        Self { code: code.to_string(), message: message.to_string(), data: data.to_string(), success: success.to_vec() }
+    }
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Self::new("code", "message", "data", &[200])
+    }
+}
+
+impl Envelope for Fields {
+    fn open(&self, body: Value) -> Result<Option<Value>, EnvelopeError> {
+        let Value::Object(mut body) = body else {
+            return Err(EnvelopeError::Malformed("body is not a JSON object".to_string()));
+        };
+
+        let code = body
+            .get(&self.code)
+            .and_then(Value::as_i64)
+            .ok_or_else(|| EnvelopeError::Malformed(format!("missing or non-integer `{}`", self.code)))?;
+        if !self.success.contains(&code) {
+            let message = body.get(&self.message).and_then(Value::as_str).unwrap_or_default();
+            return Err(EnvelopeError::Business(code, message.to_string()));
+        }
+
+        Ok(body.remove(&self.data).filter(|v| !v.is_null()))
+    }
+}
@@ -1,29 +1,93 @@
 // src/utils/fetch.rs
-use crate::utils::response::{AppError, Code};
+use crate::{
+    config::{FetchConf, RetryConf},
+    utils::{
+        breaker::Breaker,
+        envelope::{Envelope, EnvelopeError, Fields},
+        prometheus::{FETCH_REQ_COUNT, FETCH_REQ_DURATION},
+        response::{AppError, Code},
+        trace::{self, REQUEST_ID, TRACEPARENT},
+    },
+};
+use chrono::{DateTime, Utc};
+use rand::Rng;
 use reqwest::{
-    Client, Method,
-    header::{CONTENT_TYPE, HeaderMap, HeaderValue},
+    Client, Method, Response, StatusCode, Url,
+    header::{CONTENT_TYPE, HeaderMap, HeaderValue, RETRY_AFTER},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
-use std::collections::HashMap;
-use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{Instrument, Span};
 
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct Fetch {
     client: Client,
+    retry: RetryConf,
+    breaker: Breaker,
+    envelope: Arc<dyn Envelope>,
+    // The `route` label of the calls' metrics, see `with_route`.
+    route: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct ExternalResponse<T> {
-    pub code: u32,
-    pub message: String,
-    pub data: Option<T>,
+// Why a single attempt failed.
+enum Failure {
+    Transport(reqwest::Error),
+    // Non-2xx status, with the Retry-After delay when the server sent one.
+    Http(StatusCode, Option<Duration>),
+    Decode(StatusCode, String),
+    Business(StatusCode, i64, String),
+}
+
+impl Failure {
+    // Whether the failure says something about the health of the host, as
+    // opposed to the request or the payload.
+    fn is_upstream(&self) -> bool {
+        match self {
+            Failure::Transport(e) => !e.is_builder(),
+            Failure::Http(status, _) => status.is_server_error(),
+            Failure::Decode(..) | Failure::Business(..) => false,
+        }
+    }
+
+    fn outcome(&self) -> &'static str {
+        match self {
+            Failure::Transport(_) => "transport_error",
+            Failure::Http(..) => "http_error",
+            Failure::Decode(..) => "decode_error",
+            Failure::Business(..) => "business_error",
+        }
+    }
+
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            Failure::Transport(_) => None,
+            Failure::Http(status, _) | Failure::Decode(status, _) | Failure::Business(status, ..) => Some(*status),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Transport(e) => write!(f, "transport error: {}", e),
+            Failure::Http(status, _) => write!(f, "HTTP status {}", status),
+            Failure::Decode(_, e) => write!(f, "decode error: {}", e),
+            Failure::Business(_, code, msg) => write!(f, "business error: code={}, msg={}", code, msg),
+        }
+    }
 }
 
 #[allow(dead_code)]
 impl Fetch {
-    pub fn new() -> Self {
+    pub fn new(conf: FetchConf) -> Self {
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
@@ -35,7 +99,37 @@ impl Fetch {
             .build()
             .expect("Failed to create client");
 
-        Self { client }
+        Self {
+            client,
+            retry: conf.retry,
+            breaker: Breaker::new(conf.breaker),
+            envelope: Arc::new(Fields::default()),
+            route: None,
+        }
+    }
+
+    // The same client and circuits with another retry policy, for calls that
+    // need their own limits or may repeat a POST / PATCH.
+    pub fn with_retry(&self, retry: RetryConf) -> Self {
+        Self { retry, ..self.clone() }
+    }
+
+    // The same client and circuits for an upstream that wraps its payload
+    // differently, see `utils::envelope`.
+    pub fn with_envelope(&self, envelope: impl Envelope + 'static) -> Self {
+        Self { envelope: Arc::new(envelope), ..self.clone() }
+    }
+
+    // The same client and circuits, labelling calls with `route` such as
+    // "/users/{id}" instead of a template guessed from the URL. Paths with
+    // names, tokens or short ids in them need one to keep the metrics'
+    // label values bounded.
+    pub fn with_route(&self, route: &str) -> Self {
+        Self { route: Some(route.to_string()), ..self.clone() }
+    }
+
+    pub fn breaker(&self) -> &Breaker {
+        &self.breaker
     }
 
     pub async fn request<T, B>(
@@ -47,54 +141,237 @@ impl Fetch {
         headers: Option<HeaderMap>,
     ) -> Result<T, AppError>
     where
-        T: DeserializeOwned + Default,
+        T: DeserializeOwned,
+        B: Serialize + ?Sized,
+    {
+        // Responses without data fail unless allowed, so there always is some.
+        let data = self.send(method, url, body, params, headers, false).await?;
+        data.ok_or(AppError::Logic(Code::InternalServerError))
+    }
+
+    // None only when the response has no data and `allow_empty` is set.
+    async fn send<T, B>(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&B>,
+        params: Option<&HashMap<String, String>>,
+        headers: Option<HeaderMap>,
+        allow_empty: bool,
+    ) -> Result<Option<T>, AppError>
+    where
+        T: DeserializeOwned,
+        B: Serialize + ?Sized,
+    {
+        let parsed = Url::parse(url).ok();
+        // Calls to a URL without a host fail in reqwest, not upstream.
+        let host = parsed.as_ref().and_then(|u| Some(format!("{}:{}", u.host_str()?, u.port_or_known_default()?)));
+        let call = Call {
+            host: host.clone().unwrap_or_else(|| "unknown".to_string()),
+            circuit: host,
+            route: match (&self.route, &parsed) {
+                (Some(route), _) => route.clone(),
+                (None, Some(url)) => route_template(url.path()),
+                (None, None) => "unknown".to_string(),
+            },
+            method: method.to_string(),
+            allow_empty,
+        };
+        let span = tracing::info_span!(
+            "fetch",
+            host = %call.host,
+            route = %call.route,
+            method = %call.method,
+            status = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+            attempts = tracing::field::Empty,
+        );
+
+        self.call(&call, method, url, body, params, headers).instrument(span).await
+    }
+
+    async fn call<T, B>(
+        &self,
+        call: &Call,
+        method: Method,
+        url: &str,
+        body: Option<&B>,
+        params: Option<&HashMap<String, String>>,
+        headers: Option<HeaderMap>,
+    ) -> Result<Option<T>, AppError>
+    where
+        T: DeserializeOwned,
+        B: Serialize + ?Sized,
+    {
+        let retry = &self.retry;
+        let repeatable = retry.non_idempotent || method.is_idempotent();
+
+        let mut attempt = 1;
+        loop {
+            Span::current().record("attempts", attempt);
+            let permit = match &call.circuit {
+                Some(host) => match self.breaker.allow(host) {
+                    Some(permit) => Some(permit),
+                    None => {
+                        call.observe(None, "circuit_open", None);
+                        tracing::warn!("External API {} {} rejected, circuit for {} is open", method, url, host);
+                        return Err(AppError::Logic(Code::CircuitOpen));
+                    }
+                },
+                None => None,
+            };
+
+            let start = Instant::now();
+            let result = self.attempt(method.clone(), url, body, params, headers.clone(), call.allow_empty).await;
+            let elapsed = start.elapsed();
+            if let Some(permit) = permit {
+                permit.record(!matches!(&result, Err(failure) if failure.is_upstream()));
+            }
+            let failure = match result {
+                Ok((status, data)) => {
+                    call.observe(Some(status), "success", Some(elapsed));
+                    return Ok(data);
+                }
+                Err(failure) => failure,
+            };
+            call.observe(failure.status(), failure.outcome(), Some(elapsed));
+
+            let delay = match self.retry_delay(&failure, attempt) {
+                Some(delay) if repeatable && attempt < retry.max_attempts => delay,
+                _ => {
+                    tracing::error!("External API {} {} failed after {} attempts: {}", method, url, attempt, failure);
+                    return Err(AppError::Logic(Code::InternalServerError));
+                }
+            };
+
+            tracing::warn!(
+                "External API {} {} attempt {} failed, retrying in {:?}: {}",
+                method,
+                url,
+                attempt,
+                delay,
+                failure
+            );
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn attempt<T, B>(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&B>,
+        params: Option<&HashMap<String, String>>,
+        headers: Option<HeaderMap>,
+        allow_empty: bool,
+    ) -> Result<(StatusCode, Option<T>), Failure>
+    where
+        T: DeserializeOwned,
         B: Serialize + ?Sized,
     {
         let mut rb = self.client.request(method, url);
         if let Some(p) = params {
             rb = rb.query(p);
         }
-        if let Some(h) = headers {
-            rb = rb.headers(h);
+        // Forward the inbound request's ids unless the caller set its own.
+        let mut headers = headers.unwrap_or_default();
+        if let Some(ctx) = trace::current() {
+            if !headers.contains_key(REQUEST_ID)
+                && let Ok(id) = HeaderValue::from_str(&ctx.request_id)
+            {
+                headers.insert(REQUEST_ID, id);
+            }
+            if !headers.contains_key(TRACEPARENT)
+                && let Ok(traceparent) = HeaderValue::from_str(&ctx.child_traceparent())
+            {
+                headers.insert(TRACEPARENT, traceparent);
+            }
         }
+        rb = rb.headers(headers);
         if let Some(b) = body {
             rb = rb.json(b);
         }
 
-        let resp = rb.send().await.map_err(|e| {
-            tracing::error!("Request Transport Error: {}", e);
-            AppError::Logic(Code::InternalServerError)
-        })?;
+        let resp = rb.send().await.map_err(Failure::Transport)?;
+        let status = resp.status();
 
-        if !resp.status().is_success() {
-            let status = resp.status();
+        if !status.is_success() {
+            let retry_after = retry_after(&resp);
             let error_text = resp.text().await.unwrap_or_default();
-            tracing::error!("External API HTTP Error: {} - {}", status, error_text);
-            return Err(AppError::Logic(Code::InternalServerError));
+            tracing::debug!("External API HTTP Error: {} - {}", status, error_text);
+            return Err(Failure::Http(status, retry_after));
         }
 
-        let res = resp.json::<ExternalResponse<T>>().await.map_err(|e| {
-            tracing::error!("JSON Decode Error: {}", e);
-            AppError::Logic(Code::InternalServerError)
-        })?;
+        let body = resp.bytes().await.map_err(Failure::Transport)?;
+        let body: Value = serde_json::from_slice(&body).map_err(|e| Failure::Decode(status, e.to_string()))?;
 
-        if res.code != 200 {
-            tracing::error!("External API Business Error: code={}, msg={}", res.code, res.message);
-            return Err(AppError::Logic(Code::InternalServerError));
+        let data = match self.envelope.open(body) {
+            Ok(data) => data,
+            Err(EnvelopeError::Business(code, message)) => return Err(Failure::Business(status, code, message)),
+            Err(e) => return Err(Failure::Decode(status, e.to_string())),
+        };
+
+        let data = match data {
+            Some(data) => Some(T::deserialize(data).map_err(|e| Failure::Decode(status, e.to_string()))?),
+            None if allow_empty => None,
+            None => return Err(Failure::Decode(status, "response has no data".to_string())),
+        };
+
+        Ok((status, data))
+    }
+
+    // How long to wait before the next attempt, or None when the failure is
+    // not worth retrying.
+    fn retry_delay(&self, failure: &Failure, attempt: u32) -> Option<Duration> {
+        let retry = &self.retry;
+        let max = Duration::from_millis(retry.max_backoff);
+
+        match failure {
+            Failure::Transport(e) if e.is_connect() || e.is_timeout() => {}
+            Failure::Http(status, retry_after)
+                if status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS =>
+            {
+                // A server asking for more than we are willing to wait gets no retry.
+                if let Some(after) = retry_after {
+                    return (*after <= max).then_some(*after);
+                }
+            }
+            Failure::Business(_, code, _) if retry.retry_codes.contains(code) => {}
+            _ => return None,
         }
 
-        Ok(res.data.unwrap_or_default())
+        let exp = Duration::from_millis(retry.base_backoff).saturating_mul(1 << (attempt - 1).min(16));
+        let delay = exp.min(max);
+        Some(delay.mul_f64(1.0 - retry.jitter * rand::rng().random::<f64>()))
     }
 
-    pub async fn get<T: DeserializeOwned + Default>(&self, uri: &str) -> Result<T, AppError> {
+    // `request` for callers that accept a response without data, which then
+    // yields T's default.
+    pub async fn request_or_default<T, B>(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&B>,
+        params: Option<&HashMap<String, String>>,
+        headers: Option<HeaderMap>,
+    ) -> Result<T, AppError>
+    where
+        T: DeserializeOwned + Default,
+        B: Serialize + ?Sized,
+    {
+        Ok(self.send(method, url, body, params, headers, true).await?.unwrap_or_default())
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, uri: &str) -> Result<T, AppError> {
         self.request::<T, ()>(Method::GET, uri, None, None, None).await
     }
 
-    pub async fn post<T: DeserializeOwned + Default, B: Serialize>(&self, url: &str, body: &B) -> Result<T, AppError> {
+    pub async fn post<T: DeserializeOwned, B: Serialize>(&self, url: &str, body: &B) -> Result<T, AppError> {
         self.request(Method::POST, url, Some(body), None, None).await
     }
 
-    pub async fn post_with_headers<T: DeserializeOwned + Default, B: Serialize>(
+    pub async fn post_with_headers<T: DeserializeOwned, B: Serialize>(
         &self,
         url: &str,
         body: &B,
@@ -103,3 +380,225 @@ impl Fetch {
         self.request(Method::POST, url, Some(body), None, Some(headers)).await
     }
 }
+
+// The labels shared by every attempt of one call.
+struct Call {
+    host: String,
+    route: String,
+    method: String,
+    // The breaker's key, None when the URL names no host.
+    circuit: Option<String>,
+    // Whether a response without data is a success, see `request_or_default`.
+    allow_empty: bool,
+}
+
+impl Call {
+    // Counts one attempt and, unless it never left this process, its
+    // latency; the last attempt also sets the fields of the call's span.
+    fn observe(&self, status: Option<StatusCode>, outcome: &str, elapsed: Option<Duration>) {
+        let status = status.map_or_else(|| "0".to_string(), |s| s.as_u16().to_string());
+        let labels = [self.host.as_str(), self.route.as_str(), self.method.as_str(), status.as_str(), outcome];
+
+        FETCH_REQ_COUNT.with_label_values(&labels).inc();
+        if let Some(elapsed) = elapsed {
+            FETCH_REQ_DURATION.with_label_values(&labels).observe(elapsed.as_secs_f64());
+        }
+
+        let span = Span::current();
+        span.record("status", status.as_str());
+        span.record("outcome", outcome);
+    }
+}
+
+// The path with ids replaced by `{id}`, so every user's call shares one
+// label: segments that are numbers, UUIDs or long hex strings count as ids.
+// Only a fallback for calls made without `with_route`.
+fn route_template(path: &str) -> String {
+    let template: Vec<&str> = path
+        .split('/')
+        .map(|segment| {
+            let digits = !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit());
+            let hex = segment.len() >= 16 && segment.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+            if digits || hex { "{id}" } else { segment }
+        })
+        .collect();
+    template.join("/")
+}
+
+// Retry-After as either delay-seconds or an HTTP date.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&Utc) - Utc::now()).to_std().ok().or(Some(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BreakerConf;
+    use axum::Router;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    const OK: &str = r#"{"code":200,"message":"ok","data":{"id":1}}"#;
+
+    // (status, Retry-After, body)
+    type Reply = (u16, Option<&'static str>, &'static str);
+
+    // A local upstream answering with `replies` in order and repeating the
+    // last one; yields its URL and a count of the requests it received.
+    async fn upstream(replies: Vec<Reply>) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = Router::new().fallback(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            let (status, retry_after, body) = replies[n.min(replies.len() - 1)];
+            let headers: HeaderMap =
+                retry_after.map(|v| (RETRY_AFTER, HeaderValue::from_static(v))).into_iter().collect();
+            async move { (axum::http::StatusCode::from_u16(status).unwrap(), headers, body) }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/users/1", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, hits)
+    }
+
+    fn fetch(retry: RetryConf) -> Fetch {
+        let breaker = BreakerConf { min_requests: 1000, ..Default::default() };
+        Fetch::new(FetchConf { retry, breaker })
+    }
+
+    fn retry() -> RetryConf {
+        RetryConf { base_backoff: 1, jitter: 0.0, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (url, hits) = upstream(vec![(500, None, ""), (503, None, ""), (200, None, OK)]).await;
+
+        let data: Value = fetch(retry()).get(&url).await.unwrap();
+
+        assert_eq!(data, json!({ "id": 1 }));
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let (url, hits) = upstream(vec![(500, None, "")]).await;
+
+        assert!(fetch(retry()).get::<Value>(&url).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (url, hits) = upstream(vec![(404, None, ""), (200, None, OK)]).await;
+
+        assert!(fetch(retry()).get::<Value>(&url).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn waits_for_retry_after_on_429() {
+        let (url, hits) = upstream(vec![(429, Some("1"), ""), (200, None, OK)]).await;
+
+        let start = Instant::now();
+        let data: Value = fetch(retry()).get(&url).await.unwrap();
+
+        assert_eq!(data, json!({ "id": 1 }));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_when_retry_after_exceeds_max_backoff() {
+        let (url, hits) = upstream(vec![(429, Some("5"), ""), (200, None, OK)]).await;
+
+        assert!(fetch(retry()).get::<Value>(&url).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_listed_business_codes_only() {
+        let busy: Reply = (200, None, r#"{"code":5001,"message":"busy"}"#);
+
+        let (url, hits) = upstream(vec![busy, (200, None, OK)]).await;
+        let listed = RetryConf { retry_codes: vec![5001], ..retry() };
+        assert!(fetch(listed).get::<Value>(&url).await.is_ok());
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        let (url, hits) = upstream(vec![busy, (200, None, OK)]).await;
+        assert!(fetch(retry()).get::<Value>(&url).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_post_only_when_non_idempotent() {
+        let (url, hits) = upstream(vec![(500, None, ""), (200, None, OK)]).await;
+        assert!(fetch(retry()).post::<Value, _>(&url, &json!({})).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        let (url, hits) = upstream(vec![(500, None, ""), (200, None, OK)]).await;
+        let fetch = fetch(retry()).with_retry(RetryConf { non_idempotent: true, ..retry() });
+        assert!(fetch.post::<Value, _>(&url, &json!({})).await.is_ok());
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_backoff() {
+        let fetch = fetch(RetryConf { base_backoff: 100, max_backoff: 300, jitter: 0.0, ..Default::default() });
+        let failure = Failure::Http(StatusCode::INTERNAL_SERVER_ERROR, None);
+        let delay = |attempt| fetch.retry_delay(&failure, attempt).unwrap().as_millis();
+
+        assert_eq!([delay(1), delay(2), delay(3), delay(30)], [100, 200, 300, 300]);
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_backoff() {
+        let fetch = fetch(RetryConf { base_backoff: 100, jitter: 0.5, ..Default::default() });
+        let failure = Failure::Http(StatusCode::INTERNAL_SERVER_ERROR, None);
+
+        for _ in 0..100 {
+            let delay = fetch.retry_delay(&failure, 1).unwrap();
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100), "{:?}", delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_data_fails_unless_allowed() {
+        let (url, _) = upstream(vec![(200, None, r#"{"code":200,"message":"ok"}"#)]).await;
+        let fetch = fetch(retry());
+
+        assert!(fetch.get::<Value>(&url).await.is_err());
+        assert!(fetch.get::<()>(&url).await.is_err());
+        let data: Vec<i64> = fetch.request_or_default::<_, ()>(Method::GET, &url, None, None, None).await.unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn route_template_replaces_ids() {
+        assert_eq!(route_template("/users/42/orders"), "/users/{id}/orders");
+        assert_eq!(route_template("/items/0f8fad5b-d9cb-469f-a165-70867728950e"), "/items/{id}");
+        assert_eq!(route_template("/v1/users/alice"), "/v1/users/alice");
+    }
+
+    #[tokio::test]
+    async fn explicit_route_labels_the_metrics() {
+        let (url, _) = upstream(vec![(200, None, OK)]).await;
+        let url = url.replace("/users/1", "/users/alice");
+        let count = |route| {
+            let host = url.split('/').nth(2).unwrap();
+            FETCH_REQ_COUNT.with_label_values(&[host, route, "GET", "200", "success"]).get()
+        };
+
+        fetch(retry()).with_route("/users/{name}").get::<Value>(&url).await.unwrap();
+
+        assert_eq!(count("/users/{name}"), 1);
+        assert_eq!(count("/users/alice"), 0);
+    }
+}
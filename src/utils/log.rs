@@ -7,10 +7,10 @@ use tracing_subscriber::{EnvFilter, Layer, filter::LevelFilter, fmt, prelude::*}
 pub fn init<P: AsRef<Path>>(log_path: P) {
     let log_path = log_path.as_ref();
 
-    if !log_path.exists() {
-        if let Err(e) = fs::create_dir_all(log_path) {
-            eprintln!("Failed to create log directory {}: {}", log_path.display(), e);
-        }
+    if !log_path.exists()
+        && let Err(e) = fs::create_dir_all(log_path)
+    {
+        eprintln!("Failed to create log directory {}: {}", log_path.display(), e);
     }
 
     let app_log = RollingFileAppender::new(Rotation::DAILY, log_path, "app.log");
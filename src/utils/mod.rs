@@ -1,7 +1,13 @@
+pub mod breaker;
 pub mod clio;
 pub mod common;
 pub mod connect;
+pub mod envelope;
 pub mod fetch;
 pub mod log;
 pub mod prometheus;
+pub mod reload;
 pub mod response;
+pub mod shutdown;
+pub mod trace;
+pub mod validate;
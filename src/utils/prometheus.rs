@@ -9,12 +9,13 @@ use axum::{
 };
 use lazy_static::lazy_static;
 use prometheus::{
-    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, TextEncoder, register_histogram_vec,
-    register_int_counter, register_int_counter_vec,
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, TextEncoder,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
 };
 use regex::Regex;
 use std::{sync::Arc, time::Instant};
 use tokio::time::{Duration, interval};
+use tokio_util::sync::CancellationToken;
 
 const NAMESPACE: &str = "service";
 
@@ -49,17 +50,87 @@ lazy_static! {
       .buckets(vec![100.0, 500.0, 1_000.0, 5_000.0, 10_000.0, 50_000.0]),
       &["status", "endpoint", "method"]).unwrap();
 
+  // 连接池热切换
+  pub static ref POOL_SWAP_COUNT: IntCounterVec =
+    register_int_counter_vec!(Opts::new("pool_swap_total", "Total number of connection pool swaps.")
+      .namespace(NAMESPACE),
+      &["result"]).unwrap();
+
+  // 读库选择
+  pub static ref DB_READ_SELECT_COUNT: IntCounterVec =
+    register_int_counter_vec!(Opts::new("db_read_select_total", "Total number of reads routed to each replica.")
+      .namespace(NAMESPACE),
+      &["cluster", "target"]).unwrap();
+
+  // 从库健康状态
+  pub static ref DB_REPLICA_HEALTHY: IntGaugeVec =
+    register_int_gauge_vec!(Opts::new("db_replica_healthy", "Whether a replica passes health checks (1) or is ejected (0).")
+      .namespace(NAMESPACE),
+      &["cluster", "replica"]).unwrap();
+
+  // 连接池配置
+  pub static ref POOL_SETTINGS: IntGaugeVec =
+    register_int_gauge_vec!(Opts::new("pool_settings", "Configured connection pool settings.")
+      .namespace(NAMESPACE),
+      &["backend", "name", "setting"]).unwrap();
+
+  // 健康检查耗时
+  pub static ref HEALTH_CHECK_DURATION: HistogramVec =
+    register_histogram_vec!(HistogramOpts::new("health_check_duration_seconds", "Readiness check latencies in seconds.")
+      .namespace(NAMESPACE)
+      .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0]),
+      &["check", "result"]).unwrap();
+
+  // 事务耗时
+  pub static ref DB_TX_DURATION: HistogramVec =
+    register_histogram_vec!(HistogramOpts::new("db_transaction_duration_seconds", "MySQL transaction latencies in seconds, from BEGIN to COMMIT or ROLLBACK.")
+      .namespace(NAMESPACE)
+      .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]),
+      &["cluster", "outcome"]).unwrap();
+
+  // 事务回滚
+  pub static ref DB_TX_ROLLBACK: IntCounterVec =
+    register_int_counter_vec!(Opts::new("db_transaction_rollback_total", "Total number of MySQL transaction and savepoint rollbacks.")
+      .namespace(NAMESPACE),
+      &["cluster", "scope", "reason"]).unwrap();
+
+  // 缓存与数据库不一致
+  pub static ref SETTINGS_CACHE_DIVERGENCE: IntCounterVec =
+    register_int_counter_vec!(Opts::new("settings_cache_divergence_total", "Settings writes whose Redis copy could not be synced right away.")
+      .namespace(NAMESPACE),
+      &["stage"]).unwrap();
+
+  // 外部调用计数
+  pub static ref FETCH_REQ_COUNT: IntCounterVec =
+    register_int_counter_vec!(Opts::new("fetch_request_count_total", "Total number of outbound HTTP attempts.")
+      .namespace(NAMESPACE),
+      &["host", "route", "method", "status", "outcome"]).unwrap();
+
+  // 外部调用耗时
+  pub static ref FETCH_REQ_DURATION: HistogramVec =
+    register_histogram_vec!(HistogramOpts::new("fetch_request_duration_seconds", "Outbound HTTP attempt latencies in seconds.")
+      .namespace(NAMESPACE),
+      &["host", "route", "method", "status", "outcome"]).unwrap();
+
+  // 熔断器状态
+  pub static ref FETCH_CIRCUIT_STATE: IntGaugeVec =
+    register_int_gauge_vec!(Opts::new("fetch_circuit_state", "Circuit breaker state per upstream host: 0 closed, 1 open, 2 half-open.")
+      .namespace(NAMESPACE),
+      &["host"]).unwrap();
+
   // 定义自监控指标
   static ref PROM_SENSORS_REQUESTS: IntCounterVec =
     register_int_counter_vec!(Opts::new("promhttp_metric_handler_requests_total", "Total number of scrapes by HTTP status code."), &["code"]).unwrap();
 }
 
+pub type EndpointLabelFn = Arc<dyn Fn(&Request<Body>) -> String + Send + Sync>;
+
 #[derive(Clone)]
 pub struct PromOpts {
     pub exclude_regex_status: Option<Regex>,
     pub exclude_regex_endpoint: Option<Regex>,
     pub exclude_regex_method: Option<Regex>,
-    pub endpoint_label_fn: Arc<dyn Fn(&Request<Body>) -> String + Send + Sync>,
+    pub endpoint_label_fn: EndpointLabelFn,
 }
 
 impl PromOpts {
@@ -149,12 +220,14 @@ pub async fn metrics_middleware(State(state): State<Arc<AppState>>, req: Request
     response
 }
 
-pub fn start_record_uptime() {
+pub fn start_record_uptime(shutdown: CancellationToken) {
     tokio::spawn(async move {
         let mut ticker = interval(Duration::from_secs(1));
         loop {
-            ticker.tick().await;
-            UPTIME.inc();
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = ticker.tick() => UPTIME.inc(),
+            }
         }
     });
 }
@@ -176,12 +249,11 @@ fn calc_approximate_request_size(req: &Request<Body>) -> f64 {
     if let Some(host) = req.uri().host() {
         size += host.len();
     }
-    if let Some(len) = req.headers().get(http::header::CONTENT_LENGTH) {
-        if let Ok(s) = len.to_str() {
-            if let Ok(n) = s.parse::<usize>() {
-                size += n;
-            }
-        }
+    if let Some(len) = req.headers().get(http::header::CONTENT_LENGTH)
+        && let Ok(s) = len.to_str()
+        && let Ok(n) = s.parse::<usize>()
+    {
+        size += n;
     }
 
     size as f64
@@ -0,0 +1,74 @@
+// src/utils/reload.rs
+use crate::{
+    config::Config,
+    model::domain::{AppState, CacheClient, DbClient},
+    utils::{connect::Connect, prometheus::POOL_SWAP_COUNT},
+};
+use std::sync::Arc;
+use tokio::time::{Duration, interval, sleep};
+use tokio_util::sync::CancellationToken;
+
+// How long replaced pools stay open for requests that loaded them just before the swap.
+const DRAIN_GRACE: Duration = Duration::from_secs(10);
+
+pub fn start_watcher(state: Arc<AppState>, cfg: Config, shutdown: CancellationToken) {
+    if cfg.reload_interval == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut current = cfg;
+        let mut ticker = interval(Duration::from_secs(current.reload_interval));
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = ticker.tick() => {}
+            }
+
+            let next = match tokio::task::spawn_blocking(Config::init).await {
+                Ok(Ok(next)) => next,
+                Ok(Err(e)) => {
+                    tracing::error!("Config reload failed: {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!("Config reload task failed: {}", e);
+                    continue;
+                }
+            };
+
+            if next.db == current.db && next.cache == current.cache {
+                continue;
+            }
+
+            tracing::info!("Backend endpoints changed, building new pools");
+            // Never swap in degraded pools; keep serving from the current ones instead.
+            let (db, cache) = match Connect::connect(&next, false, &shutdown).await {
+                Ok(clients) => clients,
+                Err(e) => {
+                    POOL_SWAP_COUNT.with_label_values(&["failed"]).inc();
+                    tracing::error!("Failed to build new pools, keeping the current ones: {}", e);
+                    continue;
+                }
+            };
+
+            let (old_cache, old_db) = state.repository.swap(cache, db);
+            POOL_SWAP_COUNT.with_label_values(&["success"]).inc();
+            tracing::info!("Connection pools swapped");
+
+            tokio::spawn(drain(old_cache, old_db));
+            current = next;
+        }
+    });
+}
+
+async fn drain(cache: Arc<CacheClient>, db: Arc<DbClient>) {
+    sleep(DRAIN_GRACE).await;
+
+    db.close().await;
+    cache.close();
+
+    tracing::info!("Replaced connection pools drained");
+}
@@ -1,8 +1,15 @@
 // src/utils/response.rs
+use crate::{
+    repository::Expect,
+    utils::{
+        trace,
+        validate::{self, FieldError, Validate},
+    },
+};
 use axum::{
     Json,
     extract::{FromRequest, FromRequestParts, Query, Request},
-    http::{StatusCode, request::Parts},
+    http::{StatusCode, header, request::Parts},
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
@@ -18,10 +25,14 @@ pub enum Code {
     Forbidden = 403,
     NotFound = 404,
     MethodNotAllowed = 405,
+    Conflict = 409,
+    PreconditionFailed = 412,
     UnprocessableEntity = 422,
     InternalServerError = 500,
+    ServiceUnavailable = 503,
 
     DbError = 403001,
+    CircuitOpen = 503001,
 }
 
 impl Code {
@@ -37,10 +48,14 @@ impl Code {
             Code::Forbidden => "Forbidden",
             Code::NotFound => "Not Found",
             Code::MethodNotAllowed => "Method Not Allowed",
+            Code::Conflict => "Conflict",
+            Code::PreconditionFailed => "Precondition Failed",
             Code::UnprocessableEntity => "Unprocessable Entity",
             Code::InternalServerError => "Internal Server Error",
+            Code::ServiceUnavailable => "Service Unavailable",
 
             Code::DbError => "DB Error",
+            Code::CircuitOpen => "Upstream Circuit Open",
         }
     }
 
@@ -52,10 +67,14 @@ impl Code {
             Code::Forbidden => StatusCode::FORBIDDEN,
             Code::NotFound => StatusCode::NOT_FOUND,
             Code::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Code::Conflict => StatusCode::CONFLICT,
+            Code::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
             Code::UnprocessableEntity => StatusCode::UNPROCESSABLE_ENTITY,
             Code::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            Code::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
             // Map error to 4xx / 5xx
             Code::DbError => StatusCode::INTERNAL_SERVER_ERROR,
+            Code::CircuitOpen => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 }
@@ -66,6 +85,9 @@ struct ResponseBody<T> {
     message: Cow<'static, str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     data: Option<T>,
+    // Set while handling a request that went through `trace_middleware`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
 pub struct Success<T>(pub T);
@@ -82,25 +104,37 @@ impl Success<Value> {
 
 impl<T: Serialize> IntoResponse for Success<T> {
     fn into_response(self) -> Response {
-        let body =
-            ResponseBody { code: Code::Ok.as_u32(), message: Cow::Borrowed(Code::Ok.message()), data: Some(self.0) };
+        let body = ResponseBody {
+            code: Code::Ok.as_u32(),
+            message: Cow::Borrowed(Code::Ok.message()),
+            data: Some(self.0),
+            request_id: trace::current().map(|ctx| ctx.request_id),
+        };
         (StatusCode::OK, Json(json!(body))).into_response()
     }
 }
 
 #[allow(dead_code)]
+#[derive(Debug)]
 pub enum AppError {
     Logic(Code),
     Custom(Code, String),
+    Detail(Code, Value),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (code, msg) = match self {
-            AppError::Logic(c) => (c, Cow::Borrowed(c.message())),
-            AppError::Custom(c, s) => (c, Cow::Owned(s)),
+        let (code, msg, data) = match self {
+            AppError::Logic(c) => (c, Cow::Borrowed(c.message()), None),
+            AppError::Custom(c, s) => (c, Cow::Owned(s), None),
+            AppError::Detail(c, d) => (c, Cow::Borrowed(c.message()), Some(d)),
+        };
+        let body: ResponseBody<Value> = ResponseBody {
+            code: code.as_u32(),
+            message: msg,
+            data,
+            request_id: trace::current().map(|ctx| ctx.request_id),
         };
-        let body: ResponseBody<Value> = ResponseBody { code: code.as_u32(), message: msg, data: None };
         (code.http_status(), Json(json!(body))).into_response()
     }
 }
@@ -142,12 +176,41 @@ where
             Ok(Json(value)) => Ok(SafeJson(value)),
             Err(e) => {
                 tracing::warn!("JSON parsing failed -> {}", e.body_text());
-                Err(AppError::Logic(Code::UnprocessableEntity))
+                Err(validate::rejection(vec![FieldError::root(e.body_text())]))
             }
         }
     }
 }
 
+// Deserializes the body and runs the DTO's validation rules. Any failure is a
+// 422 listing each failing field path and reason.
+pub struct ValidJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ValidJson<T>
+where
+    T: serde::de::DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        // Syntax and content type first, then the shape, so type errors carry a path.
+        let body = match Json::<Value>::from_request(req, state).await {
+            Ok(Json(body)) => body,
+            Err(e) => {
+                tracing::warn!("JSON parsing failed -> {}", e.body_text());
+                return Err(validate::rejection(vec![FieldError::root(e.body_text())]));
+            }
+        };
+        let value: T = serde_path_to_error::deserialize(body).map_err(|e| {
+            let error = FieldError { field: e.path().to_string(), reason: e.inner().to_string() };
+            validate::rejection(vec![error])
+        })?;
+
+        validate::validate(&value).map_err(validate::rejection)?;
+        Ok(ValidJson(value))
+    }
+}
+
 #[allow(dead_code)]
 pub struct SafeQuery<T>(pub T);
 
@@ -162,8 +225,135 @@ where
             Ok(Query(value)) => Ok(SafeQuery(value)),
             Err(e) => {
                 tracing::warn!("Query parsing failed -> {}", e.body_text());
-                Err(AppError::Logic(Code::UnprocessableEntity))
+                Err(validate::rejection(vec![FieldError::root(e.body_text())]))
             }
         }
     }
 }
+
+// The ETag of a settings document at `version`; version 0 is a document
+// that does not exist yet.
+pub fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+// The write precondition from If-Match: none without the header, "*" for
+// any existing document, or the single strong ETag returned by a GET. Weak
+// tags and lists can never match and fail with 412 right away.
+pub struct IfMatch(pub Expect);
+
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(header::IF_MATCH) else {
+            return Ok(IfMatch(Expect::Any));
+        };
+
+        let value = value.to_str().map(str::trim).unwrap_or_default();
+        if value == "*" {
+            return Ok(IfMatch(Expect::Exists));
+        }
+        value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .and_then(|v| v.parse().ok())
+            .map(|version| IfMatch(Expect::Version(version)))
+            .ok_or(AppError::Logic(Code::PreconditionFailed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::validate::Validator;
+    use axum::body::{Body, to_bytes};
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug)]
+    struct Profile {
+        name: String,
+        age: u32,
+        address: Address,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Address {
+        zip: String,
+    }
+
+    impl Validate for Profile {
+        fn validate(&self, v: &mut Validator) {
+            v.length("name", &self.name, 1, 3).range("age", self.age, 1, 120).check(
+                "address.zip",
+                self.address.zip.len() == 5,
+                "must have 5 digits",
+            );
+        }
+    }
+
+    // The status and `data.errors` of the response ValidJson rejects `body` with.
+    async fn rejected(body: &str) -> (StatusCode, Value) {
+        let req = Request::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let Err(error) = ValidJson::<Profile>::from_request(req, &()).await else { panic!("body was accepted") };
+        let res = error.into_response();
+
+        let status = res.status();
+        let body: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await.unwrap()).unwrap();
+        (status, body["data"]["errors"].clone())
+    }
+
+    #[tokio::test]
+    async fn type_error_carries_the_nested_path() {
+        let (status, errors) = rejected(r#"{"name": "ann", "age": 30, "address": {"zip": 12345}}"#).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            errors,
+            json!([{ "field": "address.zip", "reason": "invalid type: integer `12345`, expected a string" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn every_failing_rule_is_reported() {
+        let (status, errors) = rejected(r#"{"name": "anna", "age": 0, "address": {"zip": "123"}}"#).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            errors,
+            json!([
+                { "field": "name", "reason": "length must be between 1 and 3" },
+                { "field": "age", "reason": "must be between 1 and 120" },
+                { "field": "address.zip", "reason": "must have 5 digits" },
+            ])
+        );
+    }
+
+    // Malformed JSON and a body of the wrong type both point at the body itself.
+    #[tokio::test]
+    async fn body_level_errors_are_reported_on_the_root() {
+        for body in [r#"{"name": "#, "[]"] {
+            let (status, errors) = rejected(body).await;
+
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(errors.as_array().unwrap().len(), 1, "{}", body);
+            assert_eq!(errors[0]["field"], validate::ROOT, "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn valid_body_is_extracted() {
+        let req = Request::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"name": "ann", "age": 30, "address": {"zip": "12345"}}"#))
+            .unwrap();
+        let ValidJson(profile) = ValidJson::<Profile>::from_request(req, &()).await.unwrap();
+
+        assert_eq!((profile.name.as_str(), profile.age, profile.address.zip.as_str()), ("ann", 30, "12345"));
+    }
+}
@@ -0,0 +1,115 @@
+// src/utils/trace.rs
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use rand::Rng;
+use tracing::Instrument;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+pub const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+
+// Longest X-Request-Id taken from a client; longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Correlation data of the inbound request being handled, available in its
+// extensions and, through `current`, to everything it awaits.
+#[derive(Clone, Debug)]
+pub struct RequestContext {
+    pub request_id: String,
+    // W3C trace context: the caller's trace, or a new one, and the id of our
+    // span within it.
+    pub trace_id: String,
+    pub span_id: String,
+    pub sampled: bool,
+}
+
+impl RequestContext {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let request_id = headers
+            .get(&REQUEST_ID)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic()))
+            .map_or_else(|| random_hex(16), str::to_string);
+
+        let parent = headers.get(&TRACEPARENT).and_then(|v| v.to_str().ok()).and_then(parse_traceparent);
+        let (trace_id, sampled) = parent.unwrap_or_else(|| (random_hex(16), true));
+
+        Self { request_id, trace_id, span_id: random_hex(8), sampled }
+    }
+
+    // The traceparent for a call made from our span; each outbound call gets
+    // its own parent id.
+    pub fn child_traceparent(&self) -> String {
+        self.traceparent(&random_hex(8))
+    }
+
+    fn traceparent(&self, span_id: &str) -> String {
+        format!("00-{}-{}-{}", self.trace_id, span_id, if self.sampled { "01" } else { "00" })
+    }
+}
+
+tokio::task_local! {
+    static CONTEXT: RequestContext;
+}
+
+// The context of the request the current task is handling, if any.
+pub fn current() -> Option<RequestContext> {
+    CONTEXT.try_with(|ctx| ctx.clone()).ok()
+}
+
+// Accepts or generates X-Request-Id and traceparent, runs the request in a
+// span carrying them and echoes both on the response.
+pub async fn trace_middleware(mut req: Request, next: Next) -> Response {
+    let ctx = RequestContext::from_headers(req.headers());
+    req.extensions_mut().insert(ctx.clone());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %ctx.request_id,
+        trace_id = %ctx.trace_id,
+        method = %req.method(),
+        path = %req.uri().path(),
+    );
+    let mut response = CONTEXT.scope(ctx.clone(), next.run(req)).instrument(span).await;
+
+    let headers = response.headers_mut();
+    if let Ok(id) = HeaderValue::from_str(&ctx.request_id) {
+        headers.insert(REQUEST_ID, id);
+    }
+    if let Ok(traceparent) = HeaderValue::from_str(&ctx.traceparent(&ctx.span_id)) {
+        headers.insert(TRACEPARENT, traceparent);
+    }
+
+    response
+}
+
+// `00-<trace-id>-<parent-id>-<flags>` with lowercase hex and non-zero ids;
+// yields the trace id and whether it is sampled. Later versions may append
+// fields, so only version ff and a malformed version 00 are rejected.
+fn parse_traceparent(value: &str) -> Option<(String, bool)> {
+    let parts: Vec<&str> = value.trim().split('-').collect();
+    let [version, trace_id, parent_id, flags, ..] = parts[..] else {
+        return None;
+    };
+
+    let hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+    let zero = |s: &str| s.bytes().all(|b| b == b'0');
+    if !hex(version, 2) || version == "ff" || (version == "00" && parts.len() != 4) {
+        return None;
+    }
+    if !hex(trace_id, 32) || zero(trace_id) || !hex(parent_id, 16) || zero(parent_id) || !hex(flags, 2) {
+        return None;
+    }
+
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some((trace_id.to_string(), flags & 1 == 1))
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut rng = rand::rng();
+    (0..bytes).map(|_| format!("{:02x}", rng.random::<u8>())).collect()
+}
@@ -0,0 +1,145 @@
+// src/utils/validate.rs
+use crate::utils::response::{AppError, Code};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use std::fmt::Display;
+
+// Path of the request body itself, as serde_path_to_error prints it.
+pub const ROOT: &str = ".";
+
+// One failing field: a dotted path into the body and why it was rejected.
+#[derive(Serialize, Clone, Debug)]
+pub struct FieldError {
+    pub field: String,
+    pub reason: String,
+}
+
+impl FieldError {
+    // A failure of the body as a whole, such as malformed JSON.
+    pub fn root(reason: impl Display) -> Self {
+        Self { field: ROOT.to_string(), reason: reason.to_string() }
+    }
+}
+
+// DTOs describe their rules by feeding their fields to a Validator.
+pub trait Validate {
+    fn validate(&self, v: &mut Validator);
+}
+
+// Collects every failing rule instead of stopping at the first one.
+#[derive(Default, Debug)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+#[allow(dead_code)]
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&mut self, field: &str, ok: bool, reason: impl Display) -> &mut Self {
+        if !ok {
+            self.errors.push(FieldError { field: field.to_string(), reason: reason.to_string() });
+        }
+        self
+    }
+
+    pub fn required<T>(&mut self, field: &str, value: &Option<T>) -> &mut Self {
+        self.check(field, value.is_some(), "is required")
+    }
+
+    // Length in characters, not bytes.
+    pub fn length(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Self {
+        let len = value.chars().count();
+        self.check(field, (min..=max).contains(&len), format_args!("length must be between {} and {}", min, max))
+    }
+
+    pub fn range<T: PartialOrd + Display>(&mut self, field: &str, value: T, min: T, max: T) -> &mut Self {
+        let ok = value >= min && value <= max;
+        self.check(field, ok, format_args!("must be between {} and {}", min, max))
+    }
+
+    pub fn pattern(&mut self, field: &str, value: &str, re: &Regex) -> &mut Self {
+        self.check(field, re.is_match(value), format_args!("must match {}", re.as_str()))
+    }
+
+    pub fn keys(&mut self, field: &str, value: &Map<String, Value>, min: usize, max: usize) -> &mut Self {
+        self.check(
+            field,
+            (min..=max).contains(&value.len()),
+            format_args!("must have between {} and {} keys", min, max),
+        )
+    }
+
+    pub fn finish(self) -> Result<(), Vec<FieldError>> {
+        if self.errors.is_empty() { Ok(()) } else { Err(self.errors) }
+    }
+}
+
+pub fn validate<T: Validate>(value: &T) -> Result<(), Vec<FieldError>> {
+    let mut v = Validator::new();
+    value.validate(&mut v);
+    v.finish()
+}
+
+// 422 with the failing fields in `data.errors`.
+pub fn rejection(errors: Vec<FieldError>) -> AppError {
+    AppError::Detail(Code::UnprocessableEntity, json!({ "errors": errors }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(errors: &[FieldError]) -> Vec<(&str, &str)> {
+        errors.iter().map(|e| (e.field.as_str(), e.reason.as_str())).collect()
+    }
+
+    #[test]
+    fn every_failing_rule_is_collected() {
+        let keys = Map::from_iter([("a".to_string(), Value::Null)]);
+        let mut v = Validator::new();
+        v.length("name", "", 1, 3)
+            .range("age", 0, 1, 120)
+            .pattern("zip", "12a", &Regex::new("^[0-9]+$").unwrap())
+            .keys("settings", &keys, 2, 5)
+            .required::<u64>("uid", &None)
+            .check("ok", true, "never reported");
+
+        assert_eq!(
+            fields(&v.finish().unwrap_err()),
+            vec![
+                ("name", "length must be between 1 and 3"),
+                ("age", "must be between 1 and 120"),
+                ("zip", "must match ^[0-9]+$"),
+                ("settings", "must have between 2 and 5 keys"),
+                ("uid", "is required"),
+            ]
+        );
+    }
+
+    // Bounds are inclusive and lengths count characters, not bytes.
+    #[test]
+    fn values_on_the_bounds_pass() {
+        let keys = Map::from_iter([("a".to_string(), Value::Null), ("b".to_string(), Value::Null)]);
+        let mut v = Validator::new();
+        v.length("name", "日本語", 1, 3)
+            .range("age", 120, 1, 120)
+            .range("ratio", 0.0, 0.0, 1.0)
+            .keys("settings", &keys, 2, 2);
+
+        assert!(v.finish().is_ok());
+    }
+
+    #[test]
+    fn rejection_lists_errors_under_data() {
+        let AppError::Detail(code, data) = rejection(vec![FieldError::root("EOF while parsing")]) else {
+            panic!("expected a detailed error");
+        };
+
+        assert_eq!(code.as_u32(), 422);
+        assert_eq!(data, json!({ "errors": [{ "field": ".", "reason": "EOF while parsing" }] }));
+    }
+}